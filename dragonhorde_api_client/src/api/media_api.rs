@@ -44,10 +44,15 @@ pub trait MediaApi: Send + Sync {
     ///
     async fn media_id_put(&self, id: i64, media: models::Media) -> Result<models::Media, Error<MediaIdPutError>>;
 
-    /// PUT /media/{id}
+    /// PATCH /media/{id}
     ///
+    /// Apply changes to a piece of media, rejected with 412 if `version` no longer matches
+    async fn media_id_patch(&self, id: i64, media_patch: models::MediaPatch) -> Result<models::Media, Error<MediaIdPatchError>>;
+
+    /// GET /media/by_source
     ///
-    async fn media_id_patch(&self, id: i64, media: models::Media) -> Result<(), Error<MediaIdPutError>>;
+    /// Media with a matching source, either by url or by site and post id
+    async fn media_by_source_get(&self, url: Option<String>, site: Option<String>, post_id: Option<String>) -> Result<models::media::SearchResult, Error<MediaBySourceGetError>>;
 
     /// GET /media/{id}/thumbnail
     ///
@@ -249,7 +254,7 @@ impl MediaApi for MediaApiClient {
         }
     }
 
-    async fn media_id_patch(&self, id: i64, media: models::Media) -> Result<(), Error<MediaIdPutError>> {
+    async fn media_id_patch(&self, id: i64, media_patch: models::MediaPatch) -> Result<models::Media, Error<MediaIdPatchError>> {
         let local_var_configuration = &self.configuration;
 
         let local_var_client = &local_var_configuration.client;
@@ -260,18 +265,79 @@ impl MediaApi for MediaApiClient {
         if let Some(ref local_var_user_agent) = local_var_configuration.user_agent {
             local_var_req_builder = local_var_req_builder.header(reqwest::header::USER_AGENT, local_var_user_agent.clone());
         }
-        local_var_req_builder = local_var_req_builder.json(&media);
+        local_var_req_builder = local_var_req_builder.json(&media_patch);
 
         let local_var_req = local_var_req_builder.build()?;
         let local_var_resp = local_var_client.execute(local_var_req).await?;
 
         let local_var_status = local_var_resp.status();
+        let local_var_content_type = local_var_resp
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("application/octet-stream");
+        let local_var_content_type = super::ContentType::from(local_var_content_type);
         let local_var_content = local_var_resp.text().await?;
 
         if !local_var_status.is_client_error() && !local_var_status.is_server_error() {
-            Ok(())
+            match local_var_content_type {
+                ContentType::Json => serde_json::from_str(&local_var_content).map_err(Error::from),
+                ContentType::Text => Err(Error::from(serde_json::Error::custom("Received `text/plain` content type response that cannot be converted to `models::Media`"))),
+                ContentType::Binary => Err(Error::from(serde_json::Error::custom("Received `application/octet` content type response that cannot be converted to `models::Media`"))),
+                ContentType::Unsupported(local_var_unknown_type) => Err(Error::from(serde_json::Error::custom(format!("Received `{local_var_unknown_type}` content type response that cannot be converted to `models::Media`")))),
+            }
         } else {
-            let local_var_entity: Option<MediaIdPutError> = serde_json::from_str(&local_var_content).ok();
+            let local_var_entity: Option<MediaIdPatchError> = serde_json::from_str(&local_var_content).ok();
+            let local_var_error = ResponseContent { status: local_var_status, content: local_var_content, entity: local_var_entity };
+            Err(Error::ResponseError(local_var_error))
+        }
+    }
+
+    async fn media_by_source_get(&self, url: Option<String>, site: Option<String>, post_id: Option<String>) -> Result<models::media::SearchResult, Error<MediaBySourceGetError>> {
+        let local_var_configuration = &self.configuration;
+
+        let local_var_client = &local_var_configuration.client;
+
+        let local_var_uri_str = format!("{}/media/by_source", local_var_configuration.base_path);
+        let mut local_var_req_builder = local_var_client.request(reqwest::Method::GET, local_var_uri_str.as_str());
+
+        let mut query_pairs:Vec<(&str, String)> = Vec::new();
+        if let Some(url) = url {
+            query_pairs.push(("url", url));
+        }
+        if let Some(site) = site {
+            query_pairs.push(("site", site));
+        }
+        if let Some(post_id) = post_id {
+            query_pairs.push(("post_id", post_id));
+        }
+
+        local_var_req_builder = local_var_req_builder.query(&query_pairs);
+        if let Some(ref local_var_user_agent) = local_var_configuration.user_agent {
+            local_var_req_builder = local_var_req_builder.header(reqwest::header::USER_AGENT, local_var_user_agent.clone());
+        }
+
+        let local_var_req = local_var_req_builder.build()?;
+        let local_var_resp = local_var_client.execute(local_var_req).await?;
+
+        let local_var_status = local_var_resp.status();
+        let local_var_content_type = local_var_resp
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("application/octet-stream");
+        let local_var_content_type = super::ContentType::from(local_var_content_type);
+        let local_var_content = local_var_resp.text().await?;
+
+        if !local_var_status.is_client_error() && !local_var_status.is_server_error() {
+            match local_var_content_type {
+                ContentType::Json => serde_json::from_str(&local_var_content).map_err(Error::from),
+                ContentType::Text => Err(Error::from(serde_json::Error::custom("Received `text/plain` content type response that cannot be converted to `models::SearchResult`"))),
+                ContentType::Binary => Err(Error::from(serde_json::Error::custom("Received `application/octet` content type response that cannot be converted to `models::SearchResult`"))),
+                ContentType::Unsupported(local_var_unknown_type) => Err(Error::from(serde_json::Error::custom(format!("Received `{local_var_unknown_type}` content type response that cannot be converted to `models::SearchResult`")))),
+            }
+        } else {
+            let local_var_entity: Option<MediaBySourceGetError> = serde_json::from_str(&local_var_content).ok();
             let local_var_error = ResponseContent { status: local_var_status, content: local_var_content, entity: local_var_entity };
             Err(Error::ResponseError(local_var_error))
         }
@@ -393,6 +459,16 @@ pub enum MediaIdPutError {
     UnknownValue(serde_json::Value),
 }
 
+/// struct for typed errors of method [`media_id_patch`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MediaIdPatchError {
+    Status404(),
+    Status412(),
+    Status500(),
+    UnknownValue(serde_json::Value),
+}
+
 /// struct for typed errors of method [`media_id_sources_delete`]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -439,6 +515,16 @@ pub enum MediaIdGenericPutError {
     UnknownValue(serde_json::Value),
 }
 
+/// struct for typed errors of method [`media_by_source_get`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MediaBySourceGetError {
+    Status400(),
+    Status404(),
+    Status500(),
+    UnknownValue(serde_json::Value),
+}
+
 /// struct for typed errors of method [`media_id_thumbnail_get`]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
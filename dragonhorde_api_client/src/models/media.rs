@@ -36,10 +36,13 @@ pub struct Media {
     #[serde(rename = "tag_groups", skip_serializing_if = "Option::is_none")]
     pub tag_groups: Option<std::collections::HashMap<String, Vec<String>>>,
     #[serde(rename = "sources", skip_serializing_if = "Option::is_none")]
-    pub sources: Option<Vec<String>>,
+    pub sources: Option<Vec<models::Source>>,
     #[serde(rename = "collections", skip_serializing_if = "Option::is_none")]
     pub collections: Option<Vec<String>>,
     pub description: Option<String>,
+    /// Version of the media, incremented on every change
+    #[serde(rename = "version", skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
 }
 
 impl Media {
@@ -58,6 +61,7 @@ impl Media {
             sources: None,
             collections: None,
             description: None,
+            version: None,
         }
     }
     pub fn default() -> Media {
@@ -74,6 +78,7 @@ impl Media {
             sources: None,
             collections: None,
             description: None,
+            version: None,
         }
     }
 }
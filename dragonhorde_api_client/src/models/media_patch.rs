@@ -0,0 +1,85 @@
+/*
+ * DragonHorde
+ *
+ * No description provided (generated by Openapi Generator https://github.com/openapitools/openapi-generator)
+ *
+ * The version of the OpenAPI document: 1.0
+ *
+ * Generated by: https://openapi-generator.tech
+ */
+
+use crate::models;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// ListOperations : Values to add to and remove from a set
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ListOperations<T> {
+    #[serde(rename = "add", default)]
+    pub add: Vec<T>,
+    #[serde(rename = "remove", default)]
+    pub remove: Vec<T>,
+}
+
+/// ListPatch : Either the complete new set, or operations to apply to the current one
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ListPatch<T> {
+    Replace(Vec<T>),
+    Operations(ListOperations<T>),
+}
+
+impl<T> ListPatch<T> {
+    pub fn add(values: Vec<T>) -> Self {
+        ListPatch::Operations(ListOperations { add: values, remove: Vec::new() })
+    }
+    pub fn remove(values: Vec<T>) -> Self {
+        ListPatch::Operations(ListOperations { add: Vec::new(), remove: values })
+    }
+}
+
+/// TagOperations : Tags to add, by group, and tags to remove
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TagOperations {
+    #[serde(rename = "add", default)]
+    pub add: HashMap<String, Vec<String>>,
+    #[serde(rename = "remove", default)]
+    pub remove: Vec<String>,
+}
+
+/// TagGroupsPatch : Either the complete new tag groups, or operations to apply to the current ones
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TagGroupsPatch {
+    Operations(TagOperations),
+    Replace(HashMap<String, Vec<String>>),
+}
+
+/// MediaPatch : Changes to a piece of media
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MediaPatch {
+    #[serde(rename = "created", skip_serializing_if = "Option::is_none")]
+    pub created: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(rename = "title", skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(rename = "description", skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(rename = "creators", skip_serializing_if = "Option::is_none")]
+    pub creators: Option<ListPatch<String>>,
+    #[serde(rename = "sources", skip_serializing_if = "Option::is_none")]
+    pub sources: Option<ListPatch<models::Source>>,
+    #[serde(rename = "collections", skip_serializing_if = "Option::is_none")]
+    pub collections: Option<ListPatch<String>>,
+    #[serde(rename = "tag_groups", skip_serializing_if = "Option::is_none")]
+    pub tag_groups: Option<TagGroupsPatch>,
+    /// Version of the media this change was made against
+    #[serde(rename = "version", skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+}
+
+impl MediaPatch {
+    /// Changes to a piece of media
+    pub fn new() -> MediaPatch {
+        MediaPatch::default()
+    }
+}
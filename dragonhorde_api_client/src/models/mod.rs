@@ -1,2 +1,6 @@
 pub mod media;
-pub use self::media::Media;
\ No newline at end of file
+pub use self::media::Media;
+pub mod source;
+pub use self::source::{Source, SourceType};
+pub mod media_patch;
+pub use self::media_patch::{ListOperations, ListPatch, MediaPatch, TagGroupsPatch, TagOperations};
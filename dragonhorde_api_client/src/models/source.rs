@@ -0,0 +1,54 @@
+/*
+ * DragonHorde
+ *
+ * No description provided (generated by Openapi Generator https://github.com/openapitools/openapi-generator)
+ *
+ * The version of the OpenAPI document: 1.0
+ *
+ * Generated by: https://openapi-generator.tech
+ */
+
+use serde::{Deserialize, Serialize};
+
+/// SourceType : How a source was associated with a piece of media
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SourceType {
+    #[serde(rename = "partial_match")]
+    PartialMatch,
+    #[serde(rename = "full_match")]
+    FullMatch,
+    #[serde(rename = "upload_source")]
+    UploadSource,
+}
+
+/// Source : A known location of a piece of media
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Source {
+    #[serde(rename = "url")]
+    pub url: String,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub source_type: Option<SourceType>,
+    #[serde(rename = "title", skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(rename = "site", skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+    #[serde(rename = "post_id", skip_serializing_if = "Option::is_none")]
+    pub post_id: Option<String>,
+    /// date-time the source was added to this instance
+    #[serde(rename = "added", skip_serializing_if = "Option::is_none")]
+    pub added: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Source {
+    /// A known location of a piece of media
+    pub fn new(url: String) -> Source {
+        Source {
+            url,
+            source_type: None,
+            title: None,
+            site: None,
+            post_id: None,
+            added: None,
+        }
+    }
+}
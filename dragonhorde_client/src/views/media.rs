@@ -1,7 +1,9 @@
 use std::default::Default;
 use crate::app::{UiMode, UiState};
 use dragonhorde_api_client::api::{Api, ApiClient};
-use dragonhorde_api_client::models::Media;
+use dragonhorde_api_client::models::{
+    ListPatch, Media, MediaPatch, Source, SourceType, TagGroupsPatch, TagOperations,
+};
 use eframe::emath::{Align, Vec2};
 use egui::{Button, Modifiers, OpenUrl, Ui};
 use egui_flex::{Flex, item};
@@ -271,66 +273,30 @@ fn add_data(
     // Todo: Validate input
     tokio::spawn(async move {
         let patch = match tag.tag_type {
-            TagType::Tag => {
-                let mut tag_groups = media
-                    .lock()
-                    .unwrap()
-                    .tag_groups
-                    .clone()
-                    .or(Some(HashMap::new()))
-                    .unwrap();
-                tag_group_add(&mut tag_groups, &tag.content, &tag.group.clone().unwrap());
-                Media{
-                    tag_groups: Some(tag_groups),
-                    ..Media::default()
-                }
-            }
-            TagType::Artist => {
-                let mut new_artists = media
-                    .lock()
-                    .unwrap()
-                    .creators
-                    .clone()
-                    .or(Some(vec![]))
-                    .unwrap();
-                new_artists.push(tag.content.clone());
-                Media{
-                    creators: Some(new_artists),
-                    ..Media::default()
-                    }
-            }
-            TagType::Source => {
-                let mut new_source = media
-                    .lock()
-                    .unwrap()
-                    .sources
-                    .clone()
-                    .or(Some(vec![]))
-                    .unwrap();
-                new_source.push(tag.content.clone());
-                Media {
-                    sources: Some(new_source),
-                    ..Media::default()
-                }
-            }
-            TagType::Collection => {
-                let mut new_collections = media
-                    .lock()
-                    .unwrap()
-                    .collections
-                    .clone()
-                    .or(Some(vec![]))
-                    .unwrap();
-                new_collections.push(tag.content.clone());
-                Media {
-                    collections: Some(new_collections),
-                    ..Media::default()
-                }
-            }
+            TagType::Tag => MediaPatch {
+                tag_groups: Some(TagGroupsPatch::Operations(TagOperations {
+                    add: HashMap::from([(tag.group.clone().unwrap(), vec![tag.content.clone()])]),
+                    remove: vec![],
+                })),
+                ..MediaPatch::default()
+            },
+            TagType::Artist => MediaPatch {
+                creators: Some(ListPatch::add(vec![tag.content.clone()])),
+                ..MediaPatch::default()
+            },
+            TagType::Source => MediaPatch {
+                sources: Some(ListPatch::add(vec![Source::new(tag.content.clone())])),
+                ..MediaPatch::default()
+            },
+            TagType::Collection => MediaPatch {
+                collections: Some(ListPatch::add(vec![tag.content.clone()])),
+                ..MediaPatch::default()
+            },
         };
         match api_client.media_api().media_id_patch(id, patch).await {
-            Ok(_) => {
+            Ok(updated) => {
                 let mut m = media.lock().unwrap();
+                m.version = updated.version;
                 match tag.tag_type {
                     TagType::Tag => {
                         if let Some(tag_groups) = &mut m.tag_groups {
@@ -344,7 +310,7 @@ fn add_data(
                     }
                     TagType::Source => {
                         if let Some(sources) = &mut m.sources {
-                            sources.push(tag.content);
+                            sources.push(Source::new(tag.content));
                         }
                     }
                     TagType::Collection => {
@@ -375,66 +341,30 @@ fn delete_data(
 
     tokio::spawn(async move {
         let patch = match tag.tag_type {
-            TagType::Tag => {
-                let mut tag_groups = media
-                    .lock()
-                    .unwrap()
-                    .tag_groups
-                    .clone()
-                    .or(Some(HashMap::new()))
-                    .unwrap();
-                tag_group_delete(&mut tag_groups, &tag.content, &tag.group.clone().unwrap());
-                Media{
-                    tag_groups: Some(tag_groups),
-                    ..Media::default()
-                }
-            }
-            TagType::Artist => {
-                let mut new_artists = media
-                    .lock()
-                    .unwrap()
-                    .creators
-                    .clone()
-                    .or(Some(vec![]))
-                    .unwrap();
-                delete_value(&mut new_artists, &tag.content);
-                Media{
-                    creators: Some(new_artists),
-                    ..Media::default()
-                }
-            }
-            TagType::Source => {
-                let mut new_source = media
-                    .lock()
-                    .unwrap()
-                    .sources
-                    .clone()
-                    .or(Some(vec![]))
-                    .unwrap();
-                delete_value(&mut new_source, &tag.content);
-                Media {
-                    sources: Some(new_source),
-                    ..Media::default()
-                }
-            }
-            TagType::Collection => {
-                let mut new_collections = media
-                    .lock()
-                    .unwrap()
-                    .collections
-                    .clone()
-                    .or(Some(vec![]))
-                    .unwrap();
-                delete_value(&mut new_collections, &tag.content);
-                Media {
-                    collections: Some(new_collections),
-                    ..Media::default()
-                }
-            }
+            TagType::Tag => MediaPatch {
+                tag_groups: Some(TagGroupsPatch::Operations(TagOperations {
+                    add: HashMap::new(),
+                    remove: vec![tag.content.clone()],
+                })),
+                ..MediaPatch::default()
+            },
+            TagType::Artist => MediaPatch {
+                creators: Some(ListPatch::remove(vec![tag.content.clone()])),
+                ..MediaPatch::default()
+            },
+            TagType::Source => MediaPatch {
+                sources: Some(ListPatch::remove(vec![Source::new(tag.content.clone())])),
+                ..MediaPatch::default()
+            },
+            TagType::Collection => MediaPatch {
+                collections: Some(ListPatch::remove(vec![tag.content.clone()])),
+                ..MediaPatch::default()
+            },
         };
         match api_client.media_api().media_id_patch(id, patch).await {
-            Ok(_) => {
+            Ok(updated) => {
                 let mut m = media.lock().unwrap();
+                m.version = updated.version;
                 match tag.tag_type {
                     TagType::Tag => {
                         if let Some(tag_groups) = &mut m.tag_groups {
@@ -448,7 +378,7 @@ fn delete_data(
                     }
                     TagType::Source => {
                         if let Some(sources) = &mut m.sources {
-                            delete_value(sources, &tag.content);
+                            sources.retain(|s| s.url != tag.content);
                         }
                     }
                     TagType::Collection => {
@@ -466,46 +396,46 @@ fn delete_data(
     });
 }
 
-fn gen_source_links(sources: Vec<String>, websites: &HashMap<String, String>) -> Vec<TagEntry> {
+fn source_match_label(source_type: &Option<SourceType>) -> Option<&'static str> {
+    match source_type {
+        Some(SourceType::PartialMatch) => Some("partial match"),
+        Some(SourceType::FullMatch) => Some("full match"),
+        Some(SourceType::UploadSource) => Some("upload source"),
+        None => None,
+    }
+}
+
+fn gen_source_links(sources: Vec<Source>, websites: &HashMap<String, String>) -> Vec<TagEntry> {
     let mut links: Vec<TagEntry> = Vec::new();
-    for value in sources.iter() {
-        match Url::parse(value) {
+    for source in sources.iter() {
+        let value = &source.url;
+        let display = match Url::parse(value) {
             Ok(url) => match url.scheme() {
-                "file" => {
-                    links.push(TagEntry {
-                        display: value.clone(),
-                        content: value.clone(),
-                        tag_type: TagType::Source,
-                        ..TagEntry::default()
-                    });
-                }
+                "file" => value.clone(),
                 _ => {
                     let domain = TldExtractor::new(TldOption::default())
                         .extract(url.domain().expect("Expected Domain"))
                         .expect("Expected TLD");
-                    links.push(TagEntry {
-                        tag_type: TagType::Source,
-                        display: websites
-                            .get(
-                                format!("{}.{}", domain.domain.unwrap(), domain.suffix.unwrap())
-                                    .as_str(),
-                            )
-                            .unwrap_or_else(|| value)
-                            .clone(),
-                        content: value.clone(),
-                        ..TagEntry::default()
-                    });
+                    websites
+                        .get(
+                            format!("{}.{}", domain.domain.unwrap(), domain.suffix.unwrap())
+                                .as_str(),
+                        )
+                        .unwrap_or_else(|| value)
+                        .clone()
                 }
             },
-            Err(_) => {
-                links.push(TagEntry {
-                    tag_type: TagType::Source,
-                    display: value.clone(),
-                    content: value.clone(),
-                    ..TagEntry::default()
-                });
-            }
+            Err(_) => value.clone(),
         };
+        links.push(TagEntry {
+            tag_type: TagType::Source,
+            display: match source_match_label(&source.source_type) {
+                Some(label) => format!("{} ({})", display, label),
+                None => display,
+            },
+            content: value.clone(),
+            ..TagEntry::default()
+        });
     }
     links
 }
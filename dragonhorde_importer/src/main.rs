@@ -8,8 +8,9 @@ use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand};
 use config::{Config, File};
 use dragonhorde_api_client::api::configuration::Configuration;
-use dragonhorde_api_client::api::{Api, ApiClient};
-use dragonhorde_api_client::models::Media;
+use dragonhorde_api_client::api::media_api::MediaBySourceGetError;
+use dragonhorde_api_client::api::{Api, ApiClient, Error};
+use dragonhorde_api_client::models::{Media, Source, SourceType};
 use img_hash::HashAlg::Gradient;
 use img_hash::HasherConfig;
 use log::{LevelFilter, debug, error, info, warn};
@@ -56,7 +57,7 @@ fn sha256_hash(path: &PathBuf) -> Result<String, Box<dyn std::error::Error>> {
     let mut hasher = Sha256::new();
     hasher.update(&file);
     let hash = hasher.finalize();
-    Ok(base64::encode(hash))
+    Ok(format!("{:x}", hash))
 }
 
 async fn get_post(
@@ -89,12 +90,65 @@ async fn get_post(
     }
 }
 
+/// Look for the matched posts that are exact copies of the file on the server, returning the id of
+/// the media one is attached to. Only a 404 means a post is unknown, any other failure is returned
+/// so the file isn't uploaded again on a hiccup
+async fn find_known(
+    matched: &Vec<Model>,
+    file_sha256: &str,
+    client: &ApiClient,
+) -> Result<Option<i64>, Error<MediaBySourceGetError>> {
+    for entry in matched {
+        // A perceptual hash match may be a different version of the image, so only skip on the
+        // same file
+        if !entry
+            .sha256
+            .as_ref()
+            .is_some_and(|sha256| sha256.eq_ignore_ascii_case(file_sha256))
+        {
+            continue;
+        }
+        let site = match entry.site.as_str() {
+            "furaffinity" => "furaffinity.net",
+            "weasyl" => "weasyl.com",
+            "e621" => "e621.net",
+            _ => continue,
+        };
+        match client
+            .media_api()
+            .media_by_source_get(None, Some(site.to_string()), Some(entry.id.to_string()))
+            .await
+        {
+            Ok(found) => {
+                if let Some(media) = found.result.first() {
+                    return Ok(media.id);
+                }
+            }
+            Err(Error::ResponseError(e)) if e.status == reqwest::StatusCode::NOT_FOUND => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+/// Move a handled file into the out folder, if there is one
+fn move_out(file: &PathBuf, out_folder: &Option<PathBuf>) {
+    if let Some(out_path) = out_folder {
+        let new_file = out_path.join(file.file_name().unwrap());
+        match std::fs::rename(file, &new_file) {
+            Ok(_) => {}
+            Err(e) => {error!("Failed to move {:?} to {:?} due to {:?}", file, new_file, e);}
+        }
+    }
+}
+
 async fn make_media2(
     matched: Vec<Model>,
+    file_sha256: &str,
     sites: &SiteFactories,
 ) -> Result<Media, Box<dyn std::error::Error>> {
     let mut tags: HashMap<String, Vec<String>> = HashMap::new();
-    let mut sources: Vec<String> = Vec::new();
+    let mut sources: Vec<Source> = Vec::new();
     let mut artists: Vec<String> = Vec::new();
     let mut title: Option<String> = None;
     let mut description: Option<String> = None;
@@ -110,6 +164,12 @@ async fn make_media2(
             }
         };
 
+        // Every entry matched on perceptual hash, only a matching sha256 makes it an exact copy
+        let match_type = match &entry.sha256 {
+            Some(sha256) if sha256.eq_ignore_ascii_case(file_sha256) => SourceType::FullMatch,
+            _ => SourceType::PartialMatch,
+        };
+
         let mut post: Option<Box<dyn DragonHordeImporterSite + Send>> = None;
 
         //No point trying to load the post if we know it's deleted!
@@ -161,7 +221,10 @@ async fn make_media2(
                 Err(e) => return Err(e),
             }
 
-            sources.extend(post.get_sources()?);
+            sources.extend(post.get_sources()?.into_iter().map(|source| Source {
+                source_type: source.source_type.or(Some(match_type)),
+                ..source
+            }));
             artists.extend(post.get_artists()?);
         } else {
             // Use values from the fuzzysearch database if the information isn't available online
@@ -191,14 +254,24 @@ async fn make_media2(
                         format!("https://e621.net/posts/{}", entry.id)
                     }
                 };
-                sources.push(source);
+                sources.push(Source {
+                    source_type: Some(match_type),
+                    ..Source::new(source)
+                });
             }
         }
     }
     artists.sort_by_key(|a| a.to_lowercase());
     artists.dedup_by_key(|a| a.to_lowercase());
-    sources.sort_by_key(|s| s.to_lowercase());
-    sources.dedup_by_key(|s| s.to_lowercase());
+    sources.sort_by_key(|s| s.url.to_lowercase());
+    sources.dedup_by(|a, b| {
+        let duplicate = a.url.eq_ignore_ascii_case(&b.url);
+        if duplicate {
+            b.source_type = b.source_type.or(a.source_type);
+            b.title = b.title.take().or(a.title.take());
+        }
+        duplicate
+    });
 
     for group in tags.iter_mut() {
         group.1.sort_by_key(|t| t.to_lowercase());
@@ -226,6 +299,7 @@ async fn make_media2(
         },
         collections: None,
         description,
+        version: None,
     })
 }
 
@@ -336,7 +410,28 @@ async fn run(settings: &Config, in_folder: &PathBuf, out_folder: &Option<PathBuf
 
                 debug!("{}: {:?}, {:?}", i, &file.file_name(), &matches);
 
-                let model = match make_media2(matches, &_sites).await {
+                let file_sha256 = match sha256_hash(&file) {
+                    Ok(file_sha256) => file_sha256,
+                    Err(e) => {
+                        error!("{:?} {:?}", file, e);
+                        continue;
+                    }
+                };
+
+                match find_known(&matches, &file_sha256, &client).await {
+                    Ok(Some(known)) => {
+                        info!("{:?}: Already imported as {}", file.file_name(), known);
+                        move_out(&file, &_out_folder);
+                        continue;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        error!("{:?} {:?}", file, e);
+                        continue;
+                    }
+                }
+
+                let model = match make_media2(matches, &file_sha256, &_sites).await {
                     Ok(model) => model,
                     Err(e) => {
                         error!("{:?} {:?}", file, e);
@@ -355,13 +450,7 @@ async fn run(settings: &Config, in_folder: &PathBuf, out_folder: &Option<PathBuf
                         continue;
                     }
                 };
-                if let Some(out_path) = &_out_folder {
-                    let new_file = out_path.join(file.file_name().unwrap());
-                    match std::fs::rename(&file,  &new_file) {
-                        Ok(_) => {}
-                        Err(e) => {error!("Failed to move {:?} to {:?} due to {:?}", file, new_file, e);}
-                    }
-                }
+                move_out(&file, &_out_folder);
             }
         });
         handles.push(handle);
@@ -397,7 +486,7 @@ enum Commands {
         /// Directory or file to import
         #[arg(short, long, value_name = "PATH")]
         r#in: PathBuf,
-        /// Where to move successfully uploaded files
+        /// Where to move files once uploaded, or found to be already imported
         #[arg(short, long, value_name = "PATH")]
         r#out: Option<PathBuf>,
     },
@@ -7,6 +7,7 @@ use serde::Deserialize;
 use std::collections::HashMap;
 use std::error::Error;
 use htmd::HtmlToMarkdown;
+use dragonhorde_api_client::models::{Source, SourceType};
 
 #[derive(Clone, Debug, Deserialize)]
 struct E621File {
@@ -219,13 +220,17 @@ impl DragonHordeImporterSite for e621 {
         Ok(converter.convert(&self.description).unwrap())
     }
 
-    fn get_sources(&self) -> Result<Vec<String>, Box<dyn Error>> {
+    fn get_sources(&self) -> Result<Vec<Source>, Box<dyn Error>> {
         let mut sources = self.sources.clone()
             .into_iter()
             .filter(|s| !s.starts_with("https://d.furaffinity.net/"))
             .filter(|s| !s.starts_with("https://pbs.twimg.com/"))
-            .collect::<Vec<String>>();
-        sources.push(format!("https://e621.net/posts/{}", self.id));
+            .map(|s| Source {
+                source_type: Some(SourceType::UploadSource),
+                ..Source::new(s)
+            })
+            .collect::<Vec<Source>>();
+        sources.push(Source::new(format!("https://e621.net/posts/{}", self.id)));
         Ok(sources)
     }
 
@@ -7,6 +7,7 @@ use std::error::Error;
 use std::fmt;
 use chrono::{DateTime, Utc};
 use htmd::HtmlToMarkdown;
+use dragonhorde_api_client::models::Source;
 
 #[derive(Debug, Clone)]
 pub struct Furaffinity {
@@ -129,11 +130,14 @@ impl DragonHordeImporterSite for Furaffinity {
         Ok(converter.convert(&self.post.description).unwrap())
     }
 
-    fn get_sources(&self) -> Result<Vec<String>, Box<dyn Error>> {
-        Ok(vec![format!(
-            "https://www.furaffinity.net/view/{}/",
-            self.post.id
-        )])
+    fn get_sources(&self) -> Result<Vec<Source>, Box<dyn Error>> {
+        Ok(vec![Source {
+            title: Some(self.post.title.clone()),
+            ..Source::new(format!(
+                "https://www.furaffinity.net/view/{}/",
+                self.post.id
+            ))
+        }])
     }
 
     fn get_image(&self) -> Result<Vec<u8>, Box<dyn Error>> {
@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use dragonhorde_api_client::models::Source;
 use std::collections::HashMap;
 
 pub trait DragonHordeImporterSite {
@@ -9,7 +10,9 @@ pub trait DragonHordeImporterSite {
 
     fn get_description(&self) -> Result<String, Box<dyn std::error::Error>>;
 
-    fn get_sources(&self) -> Result<Vec<String>, Box<dyn std::error::Error>>;
+    /// Sources for this post. The post itself is returned without a type, so the caller can
+    /// record how it was matched; other sources listed on the post are `UploadSource`
+    fn get_sources(&self) -> Result<Vec<Source>, Box<dyn std::error::Error>>;
 
     fn get_image(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
 
@@ -5,6 +5,7 @@ use serde::Deserialize;
 use std::collections::HashMap;
 use std::error::Error;
 use htmd::HtmlToMarkdown;
+use dragonhorde_api_client::models::Source;
 
 #[derive(Clone, Debug, Deserialize)]
 struct WeasylAvatar {
@@ -102,11 +103,14 @@ impl DragonHordeImporterSite for Weasyl {
         Ok(converter.convert(&self.description).unwrap())
     }
 
-    fn get_sources(&self) -> Result<Vec<String>, Box<dyn Error>> {
-        Ok(vec![format!(
-            "https://www.weasyl.com/~{}/submissions/{}/",
-            &self.owner_login, self.submitid
-        )])
+    fn get_sources(&self) -> Result<Vec<Source>, Box<dyn Error>> {
+        Ok(vec![Source {
+            title: Some(self.title.clone()),
+            ..Source::new(format!(
+                "https://www.weasyl.com/~{}/submissions/{}/",
+                &self.owner_login, self.submitid
+            ))
+        }])
     }
 
     fn get_image(&self) -> Result<Vec<u8>, Box<dyn Error>> {
@@ -13,6 +13,7 @@ pub struct Model {
     pub description: Option<String>,
     pub created: DateTimeWithTimeZone,
     pub parent: Option<i64>,
+    pub saved_search: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -31,6 +32,14 @@ pub enum Relation {
     SelfRef,
     #[sea_orm(has_many = "super::media_collection::Entity")]
     MediaCollection,
+    #[sea_orm(
+        belongs_to = "super::saved_searches::Entity",
+        from = "Column::SavedSearch",
+        to = "super::saved_searches::Column::Id",
+        on_update = "NoAction",
+        on_delete = "Restrict"
+    )]
+    SavedSearches,
 }
 
 impl Related<super::collection_creators::Entity> for Entity {
@@ -51,6 +60,12 @@ impl Related<super::media_collection::Entity> for Entity {
     }
 }
 
+impl Related<super::saved_searches::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::SavedSearches.def()
+    }
+}
+
 impl Related<super::creators::Entity> for Entity {
     fn to() -> RelationDef {
         super::collection_creators::Relation::Creators.def()
@@ -19,7 +19,14 @@ pub struct Model {
     #[sea_orm(select_as = "bigint", save_as = "bit(64)")]
     pub perceptual_hash: i64,
     #[sea_orm(column_type = "JsonBinary", nullable)]
-    pub metadata: Option<Json>
+    pub metadata: Option<Json>,
+    pub version: i64,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub transparent: Option<bool>,
+    pub file_size: Option<i64>,
+    #[sea_orm(column_type = "custom(\"tsvector\")", nullable, select_as = "text")]
+    pub search_text: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -11,7 +11,10 @@ pub mod media;
 pub mod media_collection;
 pub mod media_creators;
 pub mod media_tags;
+pub mod saved_searches;
 pub mod sea_orm_active_enums;
 pub mod sources;
+pub mod tag_alias;
 pub mod tag_groups;
+pub mod tag_implications;
 pub mod tags;
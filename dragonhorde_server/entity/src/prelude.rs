@@ -9,6 +9,9 @@ pub use super::media::Entity as Media;
 pub use super::media_collection::Entity as MediaCollection;
 pub use super::media_creators::Entity as MediaCreators;
 pub use super::media_tags::Entity as MediaTags;
+pub use super::saved_searches::Entity as SavedSearches;
 pub use super::sources::Entity as Sources;
+pub use super::tag_alias::Entity as TagAlias;
 pub use super::tag_groups::Entity as TagGroups;
+pub use super::tag_implications::Entity as TagImplications;
 pub use super::tags::Entity as Tags;
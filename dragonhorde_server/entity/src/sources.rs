@@ -13,6 +13,9 @@ pub struct Model {
     pub media_id: i64,
     pub r#type: Option<SourceType>,
     pub source_title: Option<String>,
+    pub site: Option<String>,
+    pub post_id: Option<String>,
+    pub added: DateTimeWithTimeZone,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
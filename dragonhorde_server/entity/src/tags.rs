@@ -12,6 +12,7 @@ pub struct Model {
     pub tag: String,
     pub created: DateTimeWithTimeZone,
     pub group: i64,
+    pub description: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -20,6 +21,8 @@ pub enum Relation {
     CollectionTags,
     #[sea_orm(has_many = "super::media_tags::Entity")]
     MediaTags,
+    #[sea_orm(has_many = "super::tag_alias::Entity")]
+    TagAlias,
     #[sea_orm(
         belongs_to = "super::tag_groups::Entity",
         from = "Column::Group",
@@ -42,6 +45,12 @@ impl Related<super::media_tags::Entity> for Entity {
     }
 }
 
+impl Related<super::tag_alias::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::TagAlias.def()
+    }
+}
+
 impl Related<super::tag_groups::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::TagGroups.def()
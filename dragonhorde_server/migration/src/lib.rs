@@ -2,6 +2,17 @@ pub use sea_orm_migration::prelude::*;
 
 mod m20220101_000001_create_table;
 mod m20250419_233658_create_table;
+mod m20250623_000001_source_details;
+mod m20250624_000001_source_post_id;
+mod m20250625_000001_media_version;
+mod m20250626_000001_media_properties;
+mod m20250627_000001_media_search_text;
+mod m20250628_000001_saved_searches;
+mod m20250629_000001_smart_collections;
+mod m20250630_000001_trigram_autocomplete;
+mod m20250701_000001_tag_aliases;
+mod m20250702_000001_tag_implications;
+mod m20250703_000001_tag_descriptions;
 
 pub struct Migrator;
 
@@ -11,6 +22,17 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20220101_000001_create_table::Migration),
             Box::new(m20250419_233658_create_table::Migration),
+            Box::new(m20250623_000001_source_details::Migration),
+            Box::new(m20250624_000001_source_post_id::Migration),
+            Box::new(m20250625_000001_media_version::Migration),
+            Box::new(m20250626_000001_media_properties::Migration),
+            Box::new(m20250627_000001_media_search_text::Migration),
+            Box::new(m20250628_000001_saved_searches::Migration),
+            Box::new(m20250629_000001_smart_collections::Migration),
+            Box::new(m20250630_000001_trigram_autocomplete::Migration),
+            Box::new(m20250701_000001_tag_aliases::Migration),
+            Box::new(m20250702_000001_tag_implications::Migration),
+            Box::new(m20250703_000001_tag_descriptions::Migration),
         ]
     }
 }
@@ -0,0 +1,44 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            ALTER TABLE sources ADD COLUMN IF NOT EXISTS site text;
+            ALTER TABLE sources ADD COLUMN IF NOT EXISTS added timestamp with time zone NOT NULL DEFAULT now();
+
+            UPDATE sources
+            SET site = regexp_replace(lower(substring(source FROM '^[a-zA-Z][a-zA-Z0-9+.-]*://([^/:?#]+)')), '^www\.', '')
+            WHERE site IS NULL;
+
+            DELETE FROM sources a
+                USING sources b
+            WHERE a.media_id = b.media_id
+              AND a.source = b.source
+              AND a.id > b.id;
+
+            CREATE UNIQUE INDEX IF NOT EXISTS sources_media_id_source_key ON sources (media_id, source);
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            DROP INDEX IF EXISTS sources_media_id_source_key;
+            ALTER TABLE sources DROP COLUMN IF EXISTS added;
+            ALTER TABLE sources DROP COLUMN IF EXISTS site;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            ALTER TABLE sources ADD COLUMN IF NOT EXISTS post_id text;
+            CREATE INDEX IF NOT EXISTS sources_site_post_id_idx ON sources (site, post_id);
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            DROP INDEX IF EXISTS sources_site_post_id_idx;
+            ALTER TABLE sources DROP COLUMN IF EXISTS post_id;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
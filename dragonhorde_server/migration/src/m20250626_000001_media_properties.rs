@@ -0,0 +1,49 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            ALTER TABLE media
+                ADD COLUMN IF NOT EXISTS width integer
+                    GENERATED ALWAYS AS ((metadata -> 'resolution' ->> 'width')::integer) STORED,
+                ADD COLUMN IF NOT EXISTS height integer
+                    GENERATED ALWAYS AS ((metadata -> 'resolution' ->> 'height')::integer) STORED,
+                ADD COLUMN IF NOT EXISTS transparent boolean
+                    GENERATED ALWAYS AS ((metadata ->> 'transparent')::boolean) STORED,
+                ADD COLUMN IF NOT EXISTS file_size bigint
+                    GENERATED ALWAYS AS ((metadata ->> 'file_size')::bigint) STORED;
+            CREATE INDEX IF NOT EXISTS media_width_idx ON media (width);
+            CREATE INDEX IF NOT EXISTS media_height_idx ON media (height);
+            CREATE INDEX IF NOT EXISTS media_ratio_idx ON media ((width::double precision / NULLIF(height, 0)));
+            CREATE INDEX IF NOT EXISTS media_transparent_idx ON media (transparent);
+            CREATE INDEX IF NOT EXISTS media_file_size_idx ON media (file_size);
+            CREATE INDEX IF NOT EXISTS media_type_idx ON media (type);
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            DROP INDEX IF EXISTS media_type_idx;
+            DROP INDEX IF EXISTS media_ratio_idx;
+            ALTER TABLE media
+                DROP COLUMN IF EXISTS width,
+                DROP COLUMN IF EXISTS height,
+                DROP COLUMN IF EXISTS transparent,
+                DROP COLUMN IF EXISTS file_size;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            ALTER TABLE media ADD COLUMN IF NOT EXISTS search_text tsvector;
+
+            -- Titles weigh most, then source titles, then descriptions
+            CREATE OR REPLACE FUNCTION media_search_text(title text, description text, media_id bigint)
+                RETURNS tsvector
+                LANGUAGE sql
+                STABLE
+            AS $$
+            SELECT SETWEIGHT(TO_TSVECTOR('english', COALESCE(title, '')), 'A') ||
+                   SETWEIGHT(TO_TSVECTOR('english', COALESCE((SELECT STRING_AGG(source_title, ' ')
+                                                              FROM sources
+                                                              WHERE sources.media_id = media_search_text.media_id),
+                                                             '')), 'B') ||
+                   SETWEIGHT(TO_TSVECTOR('english', COALESCE(description, '')), 'C')
+            $$;
+
+            CREATE OR REPLACE FUNCTION media_search_text_update() RETURNS trigger
+                LANGUAGE plpgsql
+            AS $$
+            BEGIN
+                NEW.search_text := media_search_text(NEW.title, NEW.description, NEW.id);
+                RETURN NEW;
+            END
+            $$;
+
+            CREATE OR REPLACE FUNCTION sources_search_text_update() RETURNS trigger
+                LANGUAGE plpgsql
+            AS $$
+            BEGIN
+                IF TG_OP IN ('UPDATE', 'DELETE') THEN
+                    UPDATE media
+                    SET search_text = media_search_text(title, description, id)
+                    WHERE id = OLD.media_id;
+                END IF;
+                IF TG_OP IN ('INSERT', 'UPDATE') THEN
+                    UPDATE media
+                    SET search_text = media_search_text(title, description, id)
+                    WHERE id = NEW.media_id;
+                END IF;
+                RETURN NULL;
+            END
+            $$;
+
+            DROP TRIGGER IF EXISTS media_search_text_trigger ON media;
+            CREATE TRIGGER media_search_text_trigger
+                BEFORE INSERT OR UPDATE OF title, description
+                ON media
+                FOR EACH ROW
+            EXECUTE FUNCTION media_search_text_update();
+
+            DROP TRIGGER IF EXISTS sources_search_text_trigger ON sources;
+            CREATE TRIGGER sources_search_text_trigger
+                AFTER INSERT OR UPDATE OF source_title, media_id OR DELETE
+                ON sources
+                FOR EACH ROW
+            EXECUTE FUNCTION sources_search_text_update();
+
+            UPDATE media SET search_text = media_search_text(title, description, id);
+
+            CREATE INDEX IF NOT EXISTS media_search_text_idx ON media USING GIN (search_text);
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            DROP TRIGGER IF EXISTS sources_search_text_trigger ON sources;
+            DROP TRIGGER IF EXISTS media_search_text_trigger ON media;
+            DROP FUNCTION IF EXISTS sources_search_text_update();
+            DROP FUNCTION IF EXISTS media_search_text_update();
+            DROP FUNCTION IF EXISTS media_search_text(text, text, bigint);
+            DROP INDEX IF EXISTS media_search_text_idx;
+            ALTER TABLE media DROP COLUMN IF EXISTS search_text;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
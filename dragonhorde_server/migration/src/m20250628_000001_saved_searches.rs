@@ -0,0 +1,33 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            CREATE TABLE IF NOT EXISTS saved_searches
+            (
+                id      bigserial PRIMARY KEY,
+                name    text                     NOT NULL UNIQUE,
+                query   jsonb                    NOT NULL,
+                sort    jsonb                    NOT NULL DEFAULT '{}',
+                created timestamp with time zone NOT NULL DEFAULT now(),
+                updated timestamp with time zone NOT NULL DEFAULT now()
+            );
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(r#"DROP TABLE IF EXISTS saved_searches;"#)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            ALTER TABLE collections
+                ADD COLUMN IF NOT EXISTS saved_search bigint REFERENCES saved_searches (id) ON DELETE RESTRICT;
+            CREATE INDEX IF NOT EXISTS collections_saved_search_idx ON collections (saved_search)
+                WHERE saved_search IS NOT NULL;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            DROP INDEX IF EXISTS collections_saved_search_idx;
+            ALTER TABLE collections DROP COLUMN IF EXISTS saved_search;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            CREATE EXTENSION IF NOT EXISTS pg_trgm;
+            CREATE INDEX IF NOT EXISTS tags_tag_trgm_idx ON tags USING gin (tag gin_trgm_ops);
+            CREATE INDEX IF NOT EXISTS creator_alias_alias_trgm_idx ON creator_alias USING gin (alias gin_trgm_ops);
+            CREATE INDEX IF NOT EXISTS creators_name_trgm_idx ON creators USING gin (LOWER(name) gin_trgm_ops);
+            CREATE INDEX IF NOT EXISTS collections_name_trgm_idx ON collections USING gin (LOWER(name) gin_trgm_ops);
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            DROP INDEX IF EXISTS tags_tag_trgm_idx;
+            DROP INDEX IF EXISTS creator_alias_alias_trgm_idx;
+            DROP INDEX IF EXISTS creators_name_trgm_idx;
+            DROP INDEX IF EXISTS collections_name_trgm_idx;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
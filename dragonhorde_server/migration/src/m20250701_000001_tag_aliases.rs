@@ -0,0 +1,56 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            CREATE TABLE IF NOT EXISTS tag_alias
+            (
+                id      bigserial PRIMARY KEY,
+                tag     bigint                   NOT NULL REFERENCES tags (id) ON DELETE CASCADE,
+                alias   text                     NOT NULL UNIQUE,
+                created timestamp with time zone NOT NULL DEFAULT now()
+            );
+            CREATE INDEX IF NOT EXISTS tag_alias_tag_idx ON tag_alias (tag);
+            CREATE INDEX IF NOT EXISTS tag_alias_alias_trgm_idx ON tag_alias USING gin (alias gin_trgm_ops);
+
+            -- Move everything tagged with one tag over to another, and remove the first
+            CREATE OR REPLACE PROCEDURE merge_tags(source bigint, target bigint)
+                LANGUAGE plpgsql
+            AS $$
+            BEGIN
+                INSERT INTO media_tags(media_id, tag_id)
+                SELECT media_id, target FROM media_tags WHERE tag_id = source
+                ON CONFLICT DO NOTHING;
+                INSERT INTO collection_tags(collection_id, tag_id)
+                SELECT collection_id, target FROM collection_tags WHERE tag_id = source
+                ON CONFLICT DO NOTHING;
+                UPDATE tag_alias SET tag = target WHERE tag = source;
+                DELETE FROM media_tags WHERE tag_id = source;
+                DELETE FROM collection_tags WHERE tag_id = source;
+                DELETE FROM tags WHERE id = source;
+            END
+            $$;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            DROP PROCEDURE IF EXISTS merge_tags(bigint, bigint);
+            DROP TABLE IF EXISTS tag_alias;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Merging tags, from before implications
+const MERGE_TAGS: &str = r#"
+CREATE OR REPLACE PROCEDURE merge_tags(source bigint, target bigint)
+    LANGUAGE plpgsql
+AS $$
+BEGIN
+    INSERT INTO media_tags(media_id, tag_id)
+    SELECT media_id, target FROM media_tags WHERE tag_id = source
+    ON CONFLICT DO NOTHING;
+    INSERT INTO collection_tags(collection_id, tag_id)
+    SELECT collection_id, target FROM collection_tags WHERE tag_id = source
+    ON CONFLICT DO NOTHING;
+    UPDATE tag_alias SET tag = target WHERE tag = source;
+    DELETE FROM media_tags WHERE tag_id = source;
+    DELETE FROM collection_tags WHERE tag_id = source;
+    DELETE FROM tags WHERE id = source;
+END
+$$;
+"#;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"
+            CREATE TABLE IF NOT EXISTS tag_implications
+            (
+                id      bigserial PRIMARY KEY,
+                tag     bigint                   NOT NULL REFERENCES tags (id) ON DELETE CASCADE,
+                implies bigint                   NOT NULL REFERENCES tags (id) ON DELETE CASCADE,
+                created timestamp with time zone NOT NULL DEFAULT now(),
+                UNIQUE (tag, implies),
+                CHECK (tag <> implies)
+            );
+            CREATE INDEX IF NOT EXISTS tag_implications_implies_idx ON tag_implications (implies);
+
+            -- Merged tags keep their implications, under the tag they were merged into
+            CREATE OR REPLACE PROCEDURE merge_tags(source bigint, target bigint)
+                LANGUAGE plpgsql
+            AS $$
+            BEGIN
+                INSERT INTO media_tags(media_id, tag_id)
+                SELECT media_id, target FROM media_tags WHERE tag_id = source
+                ON CONFLICT DO NOTHING;
+                INSERT INTO collection_tags(collection_id, tag_id)
+                SELECT collection_id, target FROM collection_tags WHERE tag_id = source
+                ON CONFLICT DO NOTHING;
+                UPDATE tag_alias SET tag = target WHERE tag = source;
+                INSERT INTO tag_implications(tag, implies)
+                SELECT target, implies FROM tag_implications WHERE tag = source AND implies <> target
+                ON CONFLICT DO NOTHING;
+                INSERT INTO tag_implications(tag, implies)
+                SELECT tag, target FROM tag_implications WHERE implies = source AND tag <> target
+                ON CONFLICT DO NOTHING;
+                DELETE FROM media_tags WHERE tag_id = source;
+                DELETE FROM collection_tags WHERE tag_id = source;
+                DELETE FROM tags WHERE id = source;
+            END
+            $$;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(MERGE_TAGS).await?;
+        db.execute_unprepared(r#"DROP TABLE IF EXISTS tag_implications;"#)
+            .await?;
+        Ok(())
+    }
+}
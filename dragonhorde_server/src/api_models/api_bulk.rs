@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+use crate::api_models::{ApiSource, ListOperations, SearchQueryJson, TagOperations};
+
+/// Operations to apply to every item in a set of media
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Deserialize)]
+#[schema(title = "BulkEdit")]
+pub struct ApiBulkEdit {
+    /// Media to change. Exactly one of ids and query must be given
+    pub ids: Option<Vec<i64>>,
+    /// Change every media item matching this search
+    pub query: Option<SearchQueryJson>,
+    pub creators: Option<ListOperations<String>>,
+    pub sources: Option<ListOperations<ApiSource>>,
+    pub collections: Option<ListOperations<String>>,
+    pub tag_groups: Option<TagOperations>,
+    /// Work out what would change, without saving anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize)]
+#[schema(title = "BulkEditResult")]
+pub struct ApiBulkEditResult {
+    /// Number of media items selected
+    pub matched: i64,
+    /// Number of media items that were, or on a dry run would be, changed
+    pub changed: i64,
+    /// Ids of the changed items
+    pub ids: Vec<i64>,
+    pub dry_run: bool,
+}
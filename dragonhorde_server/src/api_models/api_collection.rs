@@ -34,6 +34,11 @@ pub struct ApiCollection {
     #[serde(default)]
     pub children: Option<Vec<ApiCollection>>,
     pub parent: Option<i64>,
+    /// Saved search whose results are the collection's media, making it a smart collection, null
+    /// turns a smart collection back into a plain one
+    #[schema(value_type = Option<i64>)]
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    pub saved_search: Option<Option<i64>>,
 }
 
 #[serde_with::skip_serializing_none]
@@ -67,6 +72,8 @@ pub struct ApiCollectionResult {
     #[serde(default)]
     pub children: Option<Vec<sqlx::types::Json<ApiCollectionResult>>>,
     pub parent: Option<i64>,
+    /// For smart collections, the saved search their media come from
+    pub saved_search: Option<i64>,
 }
 
 
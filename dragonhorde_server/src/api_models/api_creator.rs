@@ -61,4 +61,8 @@ impl Default for ApiCreator {
 #[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct CreatorsResults {
     pub result: Vec<ApiCreatorResult>,
+    /// Cursor for the next page, missing on the last page or when not paging
+    pub next_cursor: Option<String>,
+    /// Number of creators, when asked for
+    pub total: Option<i64>,
 }
\ No newline at end of file
@@ -3,8 +3,9 @@ use chrono::{DateTime, FixedOffset};
 
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
+use utoipa::IntoParams;
 
-pub(crate) use crate::api_models::{DataMap, DataVector};
+pub(crate) use crate::api_models::{ApiSource, DataMap, DataVector};
 
 #[skip_serializing_none]
 #[derive(
@@ -35,10 +36,9 @@ pub struct ApiMedia {
     #[schema(value_type = Option<Vec<String>>)]
     #[serde(default)]
     pub creators: Option<DataVector>,
-    /// Known source locations for this item
-    #[schema(value_type = Option<Vec<String>>)]
+    /// Known source locations for this item, either as urls or source objects
     #[serde(default)]
-    pub sources: Option<DataVector>,
+    pub sources: Option<Vec<ApiSource>>,
     /// Collections this item is in
     #[schema(value_type = Option<Vec<String>>)]
     #[serde(default)]
@@ -90,9 +90,9 @@ pub struct ApiMediaReturn {
     #[serde(default)]
     pub creators: Option<Vec<String>>,
     /// Known source locations for this item
-    #[schema(value_type = Option<Vec<String>>)]
+    #[schema(value_type = Option<Vec<ApiSource>>)]
     #[serde(default)]
-    pub sources: Option<Vec<String>>,
+    pub sources: Option<sqlx::types::Json<Vec<ApiSource>>>,
     /// Collections this item is in
     #[schema(value_type = Option<Vec<String>>)]
     #[serde(default)]
@@ -112,6 +112,10 @@ pub struct ApiMediaReturn {
     #[schema(read_only)]
     pub metadata: Option<serde_json::Value>,
     pub file_type: Option<String>,
+    /// Incremented on every change, including those made through its tags, creators and
+    /// collections, send it back with a patch to detect conflicting edits
+    #[schema(read_only)]
+    pub version: i64,
 }
 
 #[derive(
@@ -133,5 +137,20 @@ pub struct ImageResolution{
 pub struct ImageMetadata {
     pub(crate) resolution: ImageResolution,
     pub(crate) bits_per_pixel: u16,
-    pub(crate) transparent: bool
-}
\ No newline at end of file
+    pub(crate) transparent: bool,
+    /// Size of the stored file in bytes, missing for items stored before it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) file_size: Option<u64>,
+}
+
+#[derive(Debug, IntoParams, Deserialize)]
+pub struct SimilarQuery {
+    /// How much a close perceptual hash counts, against the tag and creator overlap. An identical
+    /// image scores this much more, one with every bit different nothing. 0 unless given
+    pub(crate) hash_weight: Option<f64>,
+    /// Most items to return, 20 unless given
+    pub(crate) limit: Option<i64>,
+    /// Items to skip, for further pages
+    #[serde(default)]
+    pub(crate) offset: i64,
+}
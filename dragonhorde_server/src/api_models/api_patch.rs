@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use chrono::{DateTime, FixedOffset};
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_with::skip_serializing_none;
+
+use crate::api_models::ApiSource;
+
+/// Values to add to and remove from a set, leaving anything else in it alone
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListOperations<T> {
+    #[serde(default = "Vec::new")]
+    pub add: Vec<T>,
+    #[serde(default = "Vec::new")]
+    pub remove: Vec<T>,
+}
+
+/// Either the complete new set, or operations to apply to the current one
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ListPatch<T> {
+    Replace(Vec<T>),
+    Operations(ListOperations<T>),
+}
+
+/// Tags to add, by group, and tags to remove
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TagOperations {
+    #[serde(default)]
+    pub add: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+/// Either the complete new tag groups, or operations to apply to the current ones.
+/// An object with only `add` and/or `remove` keys is always read as operations, while an empty
+/// object removes every tag
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum TagGroupsPatch {
+    Operations(TagOperations),
+    Replace(BTreeMap<String, Vec<String>>),
+}
+
+impl<'de> Deserialize<'de> for TagGroupsPatch {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let map = serde_json::Map::deserialize(deserializer)?;
+        let operations = !map.is_empty() && map.keys().all(|k| k == "add" || k == "remove");
+        let value = serde_json::Value::Object(map);
+        let patch = if operations {
+            serde_json::from_value(value).map(TagGroupsPatch::Operations)
+        } else {
+            serde_json::from_value(value).map(TagGroupsPatch::Replace)
+        };
+        patch.map_err(D::Error::custom)
+    }
+}
+
+#[skip_serializing_none]
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[schema(title = "MediaPatch")]
+pub struct ApiMediaPatch {
+    /// date-time that this item was created, if known
+    pub created: Option<DateTime<FixedOffset>>,
+    pub title: Option<String>,
+    /// Description of this item, if available
+    pub description: Option<String>,
+    #[serde(default)]
+    pub creators: Option<ListPatch<String>>,
+    /// Known source locations for this item, either as urls or source objects
+    #[serde(default)]
+    pub sources: Option<ListPatch<ApiSource>>,
+    /// Collections this item is in
+    #[serde(default)]
+    pub collections: Option<ListPatch<String>>,
+    #[serde(default)]
+    pub tag_groups: Option<TagGroupsPatch>,
+    /// Version of the item this change was made against. The patch is rejected if the item has
+    /// changed since. An If-Match header takes priority over this
+    pub version: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(json: &str) -> Result<ApiMediaPatch, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn bare_list_replaces() {
+        let patch = patch(r#"{"creators": ["someone", "someone else"]}"#).unwrap();
+        assert_eq!(
+            patch.creators,
+            Some(ListPatch::Replace(vec![
+                "someone".to_string(),
+                "someone else".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn add_and_remove_are_operations() {
+        let patch = patch(r#"{"collections": {"add": ["a/b"], "remove": ["c"]}}"#).unwrap();
+        assert_eq!(
+            patch.collections,
+            Some(ListPatch::Operations(ListOperations {
+                add: vec!["a/b".to_string()],
+                remove: vec!["c".to_string()],
+            }))
+        );
+    }
+
+    #[test]
+    fn missing_operation_defaults_to_empty() {
+        let patch = patch(r#"{"creators": {"remove": ["someone"]}}"#).unwrap();
+        assert_eq!(
+            patch.creators,
+            Some(ListPatch::Operations(ListOperations {
+                add: Vec::new(),
+                remove: vec!["someone".to_string()],
+            }))
+        );
+    }
+
+    #[test]
+    fn tag_operations() {
+        let patch =
+            patch(r#"{"tag_groups": {"add": {"species": ["dragon"]}, "remove": ["wolf"]}}"#)
+                .unwrap();
+        assert_eq!(
+            patch.tag_groups,
+            Some(TagGroupsPatch::Operations(TagOperations {
+                add: BTreeMap::from([("species".to_string(), vec!["dragon".to_string()])]),
+                remove: vec!["wolf".to_string()],
+            }))
+        );
+    }
+
+    #[test]
+    fn tag_groups_replace() {
+        let patch = patch(r#"{"tag_groups": {"species": ["dragon"], "add": ["new"]}}"#).unwrap();
+        assert_eq!(
+            patch.tag_groups,
+            Some(TagGroupsPatch::Replace(BTreeMap::from([
+                ("add".to_string(), vec!["new".to_string()]),
+                ("species".to_string(), vec!["dragon".to_string()]),
+            ])))
+        );
+    }
+
+    #[test]
+    fn empty_tag_groups_replace() {
+        let patch = patch(r#"{"tag_groups": {}}"#).unwrap();
+        assert_eq!(patch.tag_groups, Some(TagGroupsPatch::Replace(BTreeMap::new())));
+    }
+
+    #[test]
+    fn unknown_operation_is_rejected() {
+        assert!(patch(r#"{"creators": {"add": ["someone"], "rename": ["other"]}}"#).is_err());
+        assert!(patch(r#"{"collections": {"replace": ["a"]}}"#).is_err());
+    }
+
+    #[test]
+    fn absent_fields_are_left_alone() {
+        let patch = patch(r#"{"title": "new title"}"#).unwrap();
+        assert_eq!(patch.title.as_deref(), Some("new title"));
+        assert_eq!(patch.creators, None);
+        assert_eq!(patch.tag_groups, None);
+    }
+}
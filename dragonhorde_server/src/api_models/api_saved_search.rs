@@ -0,0 +1,32 @@
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+use crate::api_models::{SearchQueryJson, Sort};
+
+/// A search to keep, or changes to one. Name and query are needed to create it
+#[serde_with::skip_serializing_none]
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[schema(title = "SavedSearch")]
+pub struct ApiSavedSearch {
+    pub name: Option<String>,
+    pub query: Option<SearchQueryJson>,
+    /// Order to run it in
+    pub sort: Option<Sort>,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[schema(title = "SavedSearchResult")]
+pub struct ApiSavedSearchResult {
+    pub id: i64,
+    pub name: String,
+    pub query: SearchQueryJson,
+    pub sort: Sort,
+    pub created: DateTime<FixedOffset>,
+    pub updated: DateTime<FixedOffset>,
+}
+
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedSearchesResults {
+    pub result: Vec<ApiSavedSearchResult>,
+}
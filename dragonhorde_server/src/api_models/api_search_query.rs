@@ -2,13 +2,76 @@ use serde::{Deserialize, Serialize};
 use utoipa::IntoParams;
 use serde_with::skip_serializing_none;
 use crate::api_models::{ApiCollectionResult, ApiMediaReturn};
+use crate::query::TagMatch;
+use std::collections::{BTreeMap, HashMap};
 
+/// The query string form of [SearchQueryJson], so searches can be shared as links. Lists are given
+/// by repeating their parameter, and a missing list doesn't filter at all; the `no_*` flags ask
+/// for items with none of that kind instead
 #[derive(Debug, IntoParams, Deserialize)]
 pub struct SearchQuery {
+    /// Query string, eg. `(dragon | wyvern) creator:foo -collection:wip`. When given, tags,
+    /// creators and collections are ignored, and only media are found
+    pub(crate) q: Option<String>,
+    /// Tags to search within. Tags prefixed with - will be excluded
     #[serde(default)]
     pub(crate) tags: Vec<String>,
+    /// Creators to search within. Creators prefixed with - will be excluded
     #[serde(default)]
     pub(crate) creators: Vec<String>,
+    /// Collections to search within. Collections prefixed with - will be excluded
+    #[serde(default)]
+    pub(crate) collections: Vec<String>,
+    /// Only items without tags
+    #[serde(default)]
+    pub(crate) no_tags: bool,
+    /// Only items without creators
+    #[serde(default)]
+    pub(crate) no_creators: bool,
+    /// Only items in no collection
+    #[serde(default)]
+    pub(crate) no_collections: bool,
+    /// Also match items in sub-collections of the collections searched for
+    #[serde(default)]
+    pub(crate) recursive: bool,
+    /// Also match items with tags that imply the tags searched for
+    #[serde(default)]
+    pub(crate) implied: bool,
+    /// Whether items need all of the tags, or any of them
+    #[serde(default)]
+    pub(crate) tag_match: TagMatch,
+    /// Words to find in titles, descriptions and source titles
+    pub(crate) description: Option<String>,
+    pub(crate) created_after: Option<String>,
+    pub(crate) created_before: Option<String>,
+    pub(crate) uploaded_after: Option<String>,
+    pub(crate) uploaded_before: Option<String>,
+    /// What to search for, media unless given
+    pub(crate) query_type: Option<QueryType>,
+    /// Also count the most common tags, creators and collections of every matching item, up to
+    /// this many of each
+    pub(crate) facets: Option<u32>,
+}
+
+impl From<SearchQuery> for SearchQueryJson {
+    fn from(query: SearchQuery) -> Self {
+        SearchQueryJson {
+            tags: (!query.no_tags).then_some(query.tags),
+            tag_match: query.tag_match,
+            creators: (!query.no_creators).then_some(query.creators),
+            collections: (!query.no_collections).then_some(query.collections),
+            recursive: query.recursive,
+            implied: query.implied,
+            description: query.description,
+            created_after: query.created_after,
+            created_before: query.created_before,
+            uploaded_after: query.uploaded_after,
+            uploaded_before: query.uploaded_before,
+            q: query.q,
+            query_type: query.query_type.unwrap_or(QueryType::Media),
+            facets: query.facets,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, utoipa::ToSchema)]
@@ -23,10 +86,14 @@ impl Default for QueryType {
     fn default() -> Self {QueryType::All}
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, utoipa::ToSchema)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
 pub struct SearchQueryJson {
-    ///Tags to search within. Tags prefixed with - will be excluded
+    ///Tags to search within. Tags prefixed with - will be excluded. A tag may be qualified by its
+    /// group, eg. species:dragon
     pub(crate) tags: Option<Vec<String>>,
+    ///Whether items need all of the tags, or any of them
+    #[serde(default)]
+    pub(crate) tag_match: TagMatch,
     // #[serde(default)]
     ///Creators to search within. Creators prefixed with - will be excluded.
     /// If not included in the request, it will query for results that do not have a creator
@@ -34,9 +101,34 @@ pub struct SearchQueryJson {
     ///Collections to search within. Collections prefixed with - will be excluded.
     /// If not included in the request, it will query for results that do not have a collection
     pub(crate) collections: Option<Vec<String>>,
+    ///Also match items in sub-collections of the collections searched for, including `collection:`
+    /// terms of the query string
+    #[serde(default)]
+    pub(crate) recursive: bool,
+    ///Also match items with tags that imply the tags searched for, whether or not the implied tags
+    /// have been added to them yet
+    #[serde(default)]
+    pub(crate) implied: bool,
+    ///Words to find in titles, descriptions and source titles. Supports "quoted phrases", or, and
+    /// -excluded words. Results are ordered by relevance unless another sort is given
     pub(crate) description: Option<String>,
+    ///Only items made on or after this date, eg. 2019-05-01, or within a time ago, eg. 7d
+    pub(crate) created_after: Option<String>,
+    ///Only items made before this date
+    pub(crate) created_before: Option<String>,
+    ///Only items uploaded on or after this date, eg. 2024-06-01T12:00:00Z, or within a time ago,
+    /// eg. 7d
+    pub(crate) uploaded_after: Option<String>,
+    ///Only items uploaded before this date
+    pub(crate) uploaded_before: Option<String>,
+    ///Query string, eg. `(dragon | wyvern) creator:foo -collection:wip rating:s species:*fox*`.
+    /// When given, it takes the place of tags, creators and collections, and only media are found
+    pub(crate) q: Option<String>,
     #[serde(default)]
     pub(crate) query_type: QueryType,
+    ///Also count the most common tags, creators and collections of every matching item, up to
+    /// this many of each
+    pub(crate) facets: Option<u32>,
 }
 
 #[derive(Debug, IntoParams, Deserialize)]
@@ -47,13 +139,56 @@ pub struct HashQuery {
     pub(crate) max_distance: Option<i64>,
 }
 
+#[derive(Debug, IntoParams, Deserialize)]
+pub struct RandomQuery {
+    /// How many items to return, 1 unless given. Fewer come back if fewer match
+    pub(crate) count: Option<u32>,
+}
+
 #[skip_serializing_none]
 #[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct SearchResult {
     pub result: Vec<ApiMediaReturn>,
     pub collections: Option<Vec<ApiCollectionResult>>,
+    /// Passages of each item's title and description where a text search matched, by media id,
+    /// with the matching words wrapped in `<mark>`
+    pub snippets: Option<HashMap<i64, String>>,
+    /// Cursor for the next page of media, missing on the last page
+    pub next_cursor: Option<String>,
+    /// Cursor for the next page of collections, missing on the last page
+    pub collections_next_cursor: Option<String>,
+    /// Number of media matching the search, when asked for
+    pub total: Option<i64>,
+    /// The most common tags, creators and collections of all matching media, when asked for
+    pub facets: Option<Facets>,
+}
+
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FacetCount {
+    pub value: String,
+    /// Number of matching media with it
+    pub count: i64,
+}
+
+#[derive(utoipa::ToSchema, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Facets {
+    /// Most common tags, by tag group, most common first
+    pub tags: BTreeMap<String, Vec<FacetCount>>,
+    pub creators: Vec<FacetCount>,
+    /// By collection path
+    pub collections: Vec<FacetCount>,
 }
 
 impl Default for SearchResult {
-    fn default() -> Self {Self { result: vec![], collections: None }}
+    fn default() -> Self {
+        Self {
+            result: vec![],
+            collections: None,
+            snippets: None,
+            next_cursor: None,
+            collections_next_cursor: None,
+            total: None,
+            facets: None,
+        }
+    }
 }
\ No newline at end of file
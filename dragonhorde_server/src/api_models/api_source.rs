@@ -0,0 +1,131 @@
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::str::FromStr;
+use utoipa::IntoParams;
+
+/// How a source was associated with a media item
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceType {
+    /// Matched by perceptual hash only
+    PartialMatch,
+    /// Matched by file hash
+    FullMatch,
+    /// Listed as a source by the uploader of another source
+    UploadSource,
+}
+
+impl SourceType {
+    /// Name of the value in the `source_type` postgres enum
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SourceType::PartialMatch => "partial_match",
+            SourceType::FullMatch => "full_match",
+            SourceType::UploadSource => "upload_source",
+        }
+    }
+
+    /// How strongly this type ties the source to the media, used to pick one when merging
+    pub fn rank(&self) -> u8 {
+        match self {
+            SourceType::UploadSource => 0,
+            SourceType::PartialMatch => 1,
+            SourceType::FullMatch => 2,
+        }
+    }
+}
+
+impl FromStr for SourceType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "partial_match" => Ok(SourceType::PartialMatch),
+            "full_match" => Ok(SourceType::FullMatch),
+            "upload_source" => Ok(SourceType::UploadSource),
+            _ => Err(format!("unknown source type {}", s)),
+        }
+    }
+}
+
+#[skip_serializing_none]
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "SourceInput")]
+#[schema(title = "SourceItem")]
+pub struct ApiSource {
+    pub url: String,
+    #[serde(rename = "type")]
+    pub source_type: Option<SourceType>,
+    /// Title of the post at the source, if known
+    pub title: Option<String>,
+    /// Host the source points to, without any leading www.
+    #[schema(read_only)]
+    pub site: Option<String>,
+    /// Id of the post on the site, for sites with a known url format
+    #[schema(read_only)]
+    pub post_id: Option<String>,
+    /// date-time that this source was added
+    #[schema(read_only)]
+    pub added: Option<DateTime<FixedOffset>>,
+}
+
+/// Sources may be sent as a bare url string, or as a full object
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SourceInput {
+    Url(String),
+    Full {
+        url: String,
+        #[serde(default, rename = "type")]
+        source_type: Option<SourceType>,
+        #[serde(default)]
+        title: Option<String>,
+        #[serde(default)]
+        site: Option<String>,
+        #[serde(default)]
+        post_id: Option<String>,
+        #[serde(default)]
+        added: Option<DateTime<FixedOffset>>,
+    },
+}
+
+impl From<SourceInput> for ApiSource {
+    fn from(value: SourceInput) -> Self {
+        match value {
+            SourceInput::Url(url) => ApiSource {
+                url,
+                source_type: None,
+                title: None,
+                site: None,
+                post_id: None,
+                added: None,
+            },
+            SourceInput::Full {
+                url,
+                source_type,
+                title,
+                site,
+                post_id,
+                added,
+            } => ApiSource {
+                url,
+                source_type,
+                title,
+                site,
+                post_id,
+                added,
+            },
+        }
+    }
+}
+
+#[derive(Debug, IntoParams, Deserialize)]
+pub struct SourceQuery {
+    /// Source url, in any of the forms the site uses
+    pub(crate) url: Option<String>,
+    /// Site host without www., eg furaffinity.net. Used with post_id
+    pub(crate) site: Option<String>,
+    /// Id of the post on the site
+    pub(crate) post_id: Option<String>,
+}
@@ -0,0 +1,78 @@
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+/// Another name for a tag. Adding or searching for the alias uses the tag instead
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[schema(title = "TagAlias")]
+pub struct ApiTagAlias {
+    pub alias: String,
+    /// The tag it stands for
+    pub tag: String,
+}
+
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TagAliasResults {
+    pub result: Vec<ApiTagAlias>,
+}
+
+/// A rule that anything tagged with `tag` is also tagged with `implies`
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[schema(title = "TagImplication")]
+pub struct ApiTagImplication {
+    pub tag: String,
+    pub implies: String,
+}
+
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TagImplicationResults {
+    pub result: Vec<ApiTagImplication>,
+}
+
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[schema(title = "TagItem")]
+pub struct ApiTagResult {
+    pub id: i64,
+    pub tag: String,
+    pub group: Option<String>,
+    pub description: Option<String>,
+    pub created: DateTime<FixedOffset>,
+    /// Number of media with the tag
+    pub count: i64,
+    pub aliases: Vec<String>,
+    /// Tags this one implies directly
+    pub implies: Vec<String>,
+}
+
+/// Changes to a tag. Renaming it to another tag's name, or one of its aliases, merges it into
+/// that tag
+#[serde_with::skip_serializing_none]
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[schema(title = "TagPatch")]
+pub struct ApiTagPatch {
+    pub tag: Option<String>,
+    /// Name of the group to move it to, created if needed
+    pub group: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Tags to fold into another, moving everything tagged with them over
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ApiTagMerge {
+    pub tags: Vec<String>,
+    pub into: String,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TagsResults {
+    pub result: Vec<ApiTagResult>,
+    /// Cursor for the next page, missing on the last page or when not paging
+    pub next_cursor: Option<String>,
+    /// Number of tags, when asked for
+    pub total: Option<i64>,
+}
+
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TagsDeleted {
+    pub deleted: u64,
+}
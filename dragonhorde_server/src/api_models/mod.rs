@@ -7,10 +7,23 @@ pub mod api_creator;
 pub use api_creator::*;
 pub mod api_collection;
 pub use api_collection::*;
+pub mod api_source;
+pub use api_source::*;
+pub mod api_patch;
+pub use api_patch::*;
+pub mod api_bulk;
+pub use api_bulk::*;
+pub mod api_saved_search;
+pub use api_saved_search::*;
+pub mod api_tag;
+pub use api_tag::*;
 
 pub mod pagination;
 pub use pagination::*;
 
+pub mod sort;
+pub use sort::*;
+
 pub mod api_search_query;
 pub use api_search_query::*;
 
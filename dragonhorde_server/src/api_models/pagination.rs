@@ -1,10 +1,106 @@
-use serde::Deserialize;
+use crate::error::AppError;
+use crate::error::AppError::BadRequest;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
 use utoipa::IntoParams;
 
 #[derive(Clone, Debug, IntoParams, Deserialize)]
 pub struct Pagination {
     /// Number of Results per page
     pub(crate) per_page: Option<u64>,
-    /// Last object of previous results, provide to get next results
+    /// Number of results to skip. Prefer cursor, which doesn't shift as items are added
     pub(crate) last: Option<u64>,
-}
\ No newline at end of file
+    /// next_cursor of the previous page, to get the page after it
+    pub(crate) cursor: Option<String>,
+    /// Also count every result, exactly or as the database's estimate
+    pub(crate) total: Option<TotalCount>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TotalCount {
+    Exact,
+    /// From the query planner, much faster for large results but can be well off
+    Estimate,
+}
+
+impl Pagination {
+    pub(crate) fn limit(&self) -> i64 {
+        self.per_page.unwrap_or(20).cast_signed()
+    }
+
+    /// Results to skip, which only applies without a cursor
+    pub(crate) fn offset(&self) -> i64 {
+        match self.cursor {
+            Some(_) => 0,
+            None => self.last.unwrap_or(0).cast_signed(),
+        }
+    }
+
+    /// Whether a listing that used to return everything has been asked for a page
+    pub(crate) fn is_paged(&self) -> bool {
+        self.per_page.is_some() || self.last.is_some() || self.cursor.is_some()
+    }
+
+    /// The cursor to continue from, which must have been made for the same order
+    pub(crate) fn cursor(&self, sort: &str) -> Result<Option<Cursor>, AppError> {
+        let Some(cursor) = &self.cursor else {
+            return Ok(None);
+        };
+        let cursor: Cursor = URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .ok_or(BadRequest("invalid cursor".to_string()))?;
+        if cursor.sort != sort {
+            return Err(BadRequest(
+                "cursor is for a different order, start again from the first page".to_string(),
+            ));
+        }
+        Ok(Some(cursor))
+    }
+}
+
+/// Where a page ended, so the next one can start after it however the results have changed
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    /// The order the cursor belongs to
+    #[serde(rename = "s")]
+    pub(crate) sort: String,
+    /// Value of the sort for the last item, as text, or none if the item had no value
+    #[serde(rename = "k")]
+    pub(crate) key: Option<String>,
+    #[serde(rename = "i")]
+    pub(crate) id: i64,
+}
+
+impl Cursor {
+    pub(crate) fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("cursor should serialise"))
+    }
+
+    /// Split rows fetched with one more than the page size into the page and the cursor for the
+    /// next one, if there is a next one
+    pub(crate) fn page(
+        mut rows: Vec<(i64, Option<String>)>,
+        limit: i64,
+        sort: &str,
+    ) -> (Vec<i64>, Option<String>) {
+        let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+        let next = if rows.len() > limit {
+            rows.truncate(limit);
+            rows.last().map(|(id, key)| {
+                Cursor {
+                    sort: sort.to_string(),
+                    key: key.clone(),
+                    id: *id,
+                }
+                .encode()
+            })
+        } else {
+            None
+        };
+        (rows.into_iter().map(|(id, _)| id).collect(), next)
+    }
+}
@@ -0,0 +1,95 @@
+use crate::query::Expr;
+use serde::{Deserialize, Serialize};
+use utoipa::IntoParams;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortBy {
+    /// When the item was added to the library, the default without a text search
+    Uploaded,
+    /// When the work was made, items without a date come last
+    Created,
+    Title,
+    /// Number of pixels
+    Resolution,
+    FileSize,
+    TagCount,
+    /// Shuffled by `seed`, so pages of the same seed don't repeat items
+    Random,
+    /// Perceptual hash distance to the item given by `similar_to`
+    Similarity,
+    /// How well items match the text search, the default when there is one
+    Relevance,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, IntoParams, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Sort {
+    /// What to order results by
+    pub(crate) sort: Option<SortBy>,
+    /// Defaults to descending, except for title and similarity which put the first title and the
+    /// closest match first
+    pub(crate) order: Option<SortOrder>,
+    /// Seed for random order
+    pub(crate) seed: Option<i64>,
+    /// Media item to compare against for similarity order
+    pub(crate) similar_to: Option<i64>,
+}
+
+impl Sort {
+    /// What to order by, which is relevance for text searches unless something else is asked for
+    pub(crate) fn by(&self, expr: &Expr) -> SortBy {
+        match self.sort {
+            Some(by) => by,
+            None if !expr.text_queries().is_empty() => SortBy::Relevance,
+            None => SortBy::Uploaded,
+        }
+    }
+
+    /// Names the order for cursors, so a cursor can't be used with a different one
+    pub(crate) fn label(&self, expr: &Expr) -> String {
+        let by = self.by(expr);
+        let order = match self.order(by) {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        };
+        match by {
+            SortBy::Random => format!("random {} {}", self.seed.unwrap_or_default(), order),
+            SortBy::Similarity => {
+                format!("similarity {} {}", self.similar_to.unwrap_or_default(), order)
+            }
+            by => format!("{} {}", by.name(), order),
+        }
+    }
+
+    pub(crate) fn order(&self, by: SortBy) -> SortOrder {
+        match (self.order, by) {
+            (Some(order), _) => order,
+            (None, SortBy::Title | SortBy::Similarity) => SortOrder::Asc,
+            (None, _) => SortOrder::Desc,
+        }
+    }
+}
+
+impl SortBy {
+    fn name(&self) -> &'static str {
+        match self {
+            SortBy::Uploaded => "uploaded",
+            SortBy::Created => "created",
+            SortBy::Title => "title",
+            SortBy::Resolution => "resolution",
+            SortBy::FileSize => "file_size",
+            SortBy::TagCount => "tag_count",
+            SortBy::Random => "random",
+            SortBy::Similarity => "similarity",
+            SortBy::Relevance => "relevance",
+        }
+    }
+}
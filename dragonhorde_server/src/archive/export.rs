@@ -0,0 +1,412 @@
+use crate::api_models::Sort;
+use crate::archive::{
+    CollectionRecord, CreatorRecord, ExportFilter, Manifest, MediaCollectionRecord, MediaRecord,
+    SavedSearchRecord, SourceRecord, TagGroupRecord, TagRecord, ARCHIVE_FORMAT, ARCHIVE_VERSION,
+    COLLECTIONS, CREATORS, FILES_DIR, MANIFEST, MEDIA, SAVED_SEARCHES, TAGS, TAG_GROUPS,
+};
+use crate::query::{self, TagMatch};
+use crate::AppState;
+use anyhow::{anyhow, Context};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::path::Path;
+
+fn append_json<T: Serialize>(
+    builder: &mut tar::Builder<File>,
+    name: &str,
+    value: &T,
+) -> anyhow::Result<()> {
+    let data = serde_json::to_vec_pretty(value)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp() as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, name, &data[..])?;
+    Ok(())
+}
+
+/// Ids of the media selected by the filter
+async fn filtered_media_ids(state: &AppState, filter: &ExportFilter) -> anyhow::Result<Vec<i64>> {
+    let mut ids: Option<BTreeSet<i64>> = None;
+
+    if let Some(collection) = &filter.collection {
+        let collection_id = sqlx::query_file_scalar!(
+            "sql/endpoints/collections/get_collection_by_path.sqlx",
+            &collection
+                .split("/")
+                .map(|i| i.to_string())
+                .collect::<Vec<String>>()
+        )
+        .fetch_optional(&state.conn)
+        .await?
+        .ok_or(anyhow!("collection {} not found", collection))?;
+
+        ids = Some(
+            sqlx::query_scalar!(
+                "SELECT media_id FROM media_collection WHERE collection_id = $1",
+                collection_id
+            )
+            .fetch_all(&state.conn)
+            .await?
+            .into_iter()
+            .collect(),
+        );
+    }
+
+    if !filter.tags.is_empty() || !filter.creators.is_empty() {
+        let expr = query::from_lists(
+            Some(&filter.tags),
+            Some(&filter.creators),
+            Some(&[]),
+            TagMatch::All,
+        )
+        .map_err(|e| anyhow!("{} (entry {})", e.error, e.position))?;
+        let found: BTreeSet<i64> = query::search_ids(&expr, &Sort::default(), i64::MAX, 0, &state.conn)
+            .await?
+            .into_iter()
+            .collect();
+        ids = Some(match ids {
+            Some(ids) => ids.intersection(&found).copied().collect(),
+            None => found,
+        });
+    }
+
+    match ids {
+        Some(ids) => Ok(ids.into_iter().collect()),
+        None => Ok(sqlx::query_scalar!("SELECT id FROM media ORDER BY id")
+            .fetch_all(&state.conn)
+            .await?),
+    }
+}
+
+/// Write the media selected by the filter, along with everything they refer to, to an archive
+pub async fn export_archive(
+    state: &AppState,
+    path: &Path,
+    filter: &ExportFilter,
+) -> anyhow::Result<usize> {
+    let db = &state.conn;
+    let everything = filter.is_empty();
+    let ids = filtered_media_ids(state, filter).await?;
+
+    let mut media_creators: HashMap<i64, Vec<i64>> = HashMap::new();
+    for r in sqlx::query!(
+        "SELECT media_id, creator_id FROM media_creators WHERE media_id = ANY ($1::bigint[])",
+        &ids[..]
+    )
+    .fetch_all(db)
+    .await?
+    {
+        media_creators.entry(r.media_id).or_default().push(r.creator_id);
+    }
+
+    let mut media_tags: HashMap<i64, Vec<i64>> = HashMap::new();
+    for r in sqlx::query!(
+        "SELECT media_id, tag_id FROM media_tags WHERE media_id = ANY ($1::bigint[])",
+        &ids[..]
+    )
+    .fetch_all(db)
+    .await?
+    {
+        media_tags.entry(r.media_id).or_default().push(r.tag_id);
+    }
+
+    let mut media_collections: HashMap<i64, Vec<MediaCollectionRecord>> = HashMap::new();
+    for r in sqlx::query!(
+        "SELECT media_id, collection_id, ord FROM media_collection WHERE media_id = ANY ($1::bigint[])",
+        &ids[..]
+    )
+    .fetch_all(db)
+    .await?
+    {
+        media_collections
+            .entry(r.media_id)
+            .or_default()
+            .push(MediaCollectionRecord {
+                id: r.collection_id,
+                ord: r.ord,
+            });
+    }
+
+    let mut media_sources: HashMap<i64, Vec<SourceRecord>> = HashMap::new();
+    for r in sqlx::query!(
+        r#"SELECT media_id, source, type::text AS source_type, source_title, site, post_id, added
+           FROM sources
+           WHERE media_id = ANY ($1::bigint[])
+           ORDER BY media_id, added"#,
+        &ids[..]
+    )
+    .fetch_all(db)
+    .await?
+    {
+        media_sources.entry(r.media_id).or_default().push(SourceRecord {
+            url: r.source,
+            source_type: r.source_type,
+            title: r.source_title,
+            site: r.site,
+            post_id: r.post_id,
+            added: r.added,
+        });
+    }
+
+    let media: Vec<MediaRecord> = sqlx::query!(
+        r#"SELECT id,
+                  storage_uri,
+                  sha256,
+                  type                             AS file_type,
+                  CAST(perceptual_hash AS bigint)  AS "perceptual_hash!",
+                  uploaded,
+                  created,
+                  title,
+                  description,
+                  metadata
+           FROM media
+           WHERE id = ANY ($1::bigint[])
+           ORDER BY id"#,
+        &ids[..]
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|r| MediaRecord {
+        id: r.id,
+        file: format!("{}/{}", FILES_DIR, r.storage_uri),
+        storage_uri: r.storage_uri,
+        sha256: r.sha256,
+        file_type: r.file_type,
+        perceptual_hash: r.perceptual_hash,
+        uploaded: r.uploaded,
+        created: r.created,
+        title: r.title,
+        description: r.description,
+        metadata: r.metadata,
+        creators: media_creators.remove(&r.id).unwrap_or_default(),
+        tags: media_tags.remove(&r.id).unwrap_or_default(),
+        collections: media_collections.remove(&r.id).unwrap_or_default(),
+        sources: media_sources.remove(&r.id).unwrap_or_default(),
+    })
+    .collect();
+
+    // Collections the media are in, and every collection above them
+    let collection_ids: Vec<i64> = media
+        .iter()
+        .flat_map(|m| m.collections.iter().map(|c| c.id))
+        .collect::<BTreeSet<i64>>()
+        .into_iter()
+        .collect();
+    let collection_rows = sqlx::query!(
+        r#"
+        WITH RECURSIVE wanted AS (SELECT id, parent
+                                  FROM collections
+                                  WHERE $2 OR id = ANY ($1::bigint[])
+                                  UNION
+                                  SELECT c.id, c.parent
+                                  FROM collections c
+                                           JOIN wanted w ON c.id = w.parent)
+        SELECT id, name, description, created, parent, saved_search
+        FROM collections
+        WHERE id IN (SELECT id FROM wanted)
+        ORDER BY id"#,
+        &collection_ids[..],
+        everything
+    )
+    .fetch_all(db)
+    .await?;
+    let collection_ids: Vec<i64> = collection_rows.iter().map(|c| c.id).collect();
+
+    let mut collection_creators: HashMap<i64, Vec<i64>> = HashMap::new();
+    for r in sqlx::query!(
+        "SELECT collection_id, creator_id FROM collection_creators WHERE collection_id = ANY ($1::bigint[])",
+        &collection_ids[..]
+    )
+    .fetch_all(db)
+    .await?
+    {
+        collection_creators
+            .entry(r.collection_id)
+            .or_default()
+            .push(r.creator_id);
+    }
+
+    let mut collection_tags: HashMap<i64, Vec<i64>> = HashMap::new();
+    for r in sqlx::query!(
+        "SELECT collection_id, tag_id FROM collection_tags WHERE collection_id = ANY ($1::bigint[])",
+        &collection_ids[..]
+    )
+    .fetch_all(db)
+    .await?
+    {
+        collection_tags.entry(r.collection_id).or_default().push(r.tag_id);
+    }
+
+    let collections: Vec<CollectionRecord> = collection_rows
+        .into_iter()
+        .map(|r| CollectionRecord {
+            creators: collection_creators.remove(&r.id).unwrap_or_default(),
+            tags: collection_tags.remove(&r.id).unwrap_or_default(),
+            id: r.id,
+            name: r.name,
+            description: r.description,
+            created: r.created,
+            parent: r.parent,
+            saved_search: r.saved_search,
+        })
+        .collect();
+
+    let saved_search_ids: Vec<i64> = collections
+        .iter()
+        .filter_map(|c| c.saved_search)
+        .collect::<BTreeSet<i64>>()
+        .into_iter()
+        .collect();
+    let saved_searches: Vec<SavedSearchRecord> = sqlx::query!(
+        r#"SELECT id, name, query, sort, created, updated
+           FROM saved_searches
+           WHERE $2 OR id = ANY ($1::bigint[])
+           ORDER BY id"#,
+        &saved_search_ids[..],
+        everything
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|r| SavedSearchRecord {
+        id: r.id,
+        name: r.name,
+        query: r.query,
+        sort: r.sort,
+        created: r.created,
+        updated: r.updated,
+    })
+    .collect();
+
+    let creator_ids: Vec<i64> = media
+        .iter()
+        .flat_map(|m| m.creators.iter())
+        .chain(collections.iter().flat_map(|c| c.creators.iter()))
+        .copied()
+        .collect::<BTreeSet<i64>>()
+        .into_iter()
+        .collect();
+    let creators: Vec<CreatorRecord> = sqlx::query!(
+        r#"SELECT creators.id,
+                  creators.name,
+                  creators.sites,
+                  creators.created,
+                  ARRAY_REMOVE(ARRAY_AGG(creator_alias.alias), NULL) AS "aliases!"
+           FROM creators
+                    LEFT JOIN creator_alias ON creator_alias.creator = creators.id
+           WHERE $2 OR creators.id = ANY ($1::bigint[])
+           GROUP BY creators.id
+           ORDER BY creators.id"#,
+        &creator_ids[..],
+        everything
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|r| CreatorRecord {
+        id: r.id,
+        name: r.name,
+        sites: r.sites,
+        created: r.created,
+        aliases: r.aliases,
+    })
+    .collect();
+
+    let tag_ids: Vec<i64> = media
+        .iter()
+        .flat_map(|m| m.tags.iter())
+        .chain(collections.iter().flat_map(|c| c.tags.iter()))
+        .copied()
+        .collect::<BTreeSet<i64>>()
+        .into_iter()
+        .collect();
+    // Along with every tag they imply, so their implications can be kept
+    let tag_ids: Vec<i64> = sqlx::query_scalar!(
+        r#"WITH RECURSIVE implied AS (SELECT UNNEST($1::bigint[]) AS id
+                                      UNION
+                                      SELECT tag_implications.implies
+                                      FROM tag_implications
+                                               JOIN implied ON tag_implications.tag = implied.id)
+           SELECT id AS "id!" FROM implied"#,
+        &tag_ids[..]
+    )
+    .fetch_all(db)
+    .await?;
+    let tags: Vec<TagRecord> = sqlx::query!(
+        r#"SELECT tags.id,
+                  tags.tag,
+                  tags."group",
+                  tags.description,
+                  tags.created,
+                  ARRAY_REMOVE(ARRAY_AGG(tag_alias.alias), NULL) AS "aliases!",
+                  ARRAY(SELECT implies
+                        FROM tag_implications
+                        WHERE tag_implications.tag = tags.id
+                        ORDER BY implies)                   AS "implies!"
+           FROM tags
+                    LEFT JOIN tag_alias ON tag_alias.tag = tags.id
+           WHERE $2 OR tags.id = ANY ($1::bigint[])
+           GROUP BY tags.id
+           ORDER BY tags.id"#,
+        &tag_ids[..],
+        everything
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|r| TagRecord {
+        id: r.id,
+        tag: r.tag,
+        group: r.group,
+        description: r.description,
+        created: r.created,
+        aliases: r.aliases,
+        implies: r.implies,
+    })
+    .collect();
+
+    let tag_groups: Vec<TagGroupRecord> =
+        sqlx::query!("SELECT id, name, description, created FROM tag_groups ORDER BY id")
+            .fetch_all(db)
+            .await?
+            .into_iter()
+            .map(|r| TagGroupRecord {
+                id: r.id,
+                name: r.name,
+                description: r.description,
+                created: r.created,
+            })
+            .collect();
+
+    // Records go first, so an import can read them all before it reaches the files
+    let mut builder = tar::Builder::new(File::create(path)?);
+    append_json(
+        &mut builder,
+        MANIFEST,
+        &Manifest {
+            format: ARCHIVE_FORMAT.to_string(),
+            version: ARCHIVE_VERSION,
+            exported: chrono::Utc::now(),
+            media: media.len(),
+        },
+    )?;
+    append_json(&mut builder, TAG_GROUPS, &tag_groups)?;
+    append_json(&mut builder, TAGS, &tags)?;
+    append_json(&mut builder, CREATORS, &creators)?;
+    append_json(&mut builder, SAVED_SEARCHES, &saved_searches)?;
+    append_json(&mut builder, COLLECTIONS, &collections)?;
+    append_json(&mut builder, MEDIA, &media)?;
+
+    for item in &media {
+        builder
+            .append_path_with_name(state.storage_dir.join(&item.storage_uri), &item.file)
+            .with_context(|| format!("adding file for media {}", item.id))?;
+    }
+    builder.into_inner()?.sync_all()?;
+
+    Ok(media.len())
+}
@@ -0,0 +1,525 @@
+use crate::archive::{
+    CollectionRecord, CreatorRecord, Manifest, MediaRecord, SavedSearchRecord, TagGroupRecord,
+    TagRecord, ARCHIVE_FORMAT, ARCHIVE_VERSION, COLLECTIONS, CREATORS, MANIFEST, MEDIA,
+    SAVED_SEARCHES, TAGS, TAG_GROUPS,
+};
+use crate::AppState;
+use anyhow::{anyhow, bail};
+use dragonhorde_common::hash::sha256;
+use image::imageops::Lanczos3;
+use serde::de::DeserializeOwned;
+use sqlx::types::BitVec;
+use sqlx::{Postgres, Transaction};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    /// Ids of every media item in the archive, as they are on this server
+    pub media_ids: Vec<i64>,
+    pub media_added: usize,
+    pub media_merged: usize,
+    pub tags: usize,
+    pub creators: usize,
+    pub collections: usize,
+}
+
+#[derive(Default)]
+struct ArchiveRecords {
+    manifest: Option<Manifest>,
+    tag_groups: Vec<TagGroupRecord>,
+    tags: Vec<TagRecord>,
+    creators: Vec<CreatorRecord>,
+    saved_searches: Vec<SavedSearchRecord>,
+    collections: Vec<CollectionRecord>,
+    media: Vec<MediaRecord>,
+}
+
+/// A media item created by the import, still waiting on its file
+struct PendingFile {
+    sha256: String,
+    storage_uri: String,
+}
+
+/// The name a file is stored under, rebuilt from its hash and type the way uploads are named,
+/// so an archive can't place files outside of storage
+fn storage_name(sha256: &str, storage_uri: &str) -> anyhow::Result<String> {
+    if sha256.is_empty() || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("{} is not a valid sha256", sha256);
+    }
+    let mut file_name = PathBuf::from(sha256);
+    if let Some(extension) = Path::new(storage_uri).extension() {
+        if !extension.to_string_lossy().chars().all(|c| c.is_ascii_alphanumeric()) {
+            bail!("{} does not have a valid extension", storage_uri);
+        }
+        file_name.set_extension(extension);
+    }
+    Ok(file_name.to_string_lossy().to_string())
+}
+
+fn read_json<T: DeserializeOwned>(entry: impl Read) -> anyhow::Result<T> {
+    Ok(serde_json::from_reader(entry)?)
+}
+
+fn read_records(path: &Path) -> anyhow::Result<ArchiveRecords> {
+    let mut records = ArchiveRecords::default();
+    let mut archive = tar::Archive::new(File::open(path)?);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        match name.as_str() {
+            MANIFEST => records.manifest = Some(read_json(entry)?),
+            TAG_GROUPS => records.tag_groups = read_json(entry)?,
+            TAGS => records.tags = read_json(entry)?,
+            CREATORS => records.creators = read_json(entry)?,
+            SAVED_SEARCHES => records.saved_searches = read_json(entry)?,
+            COLLECTIONS => records.collections = read_json(entry)?,
+            MEDIA => records.media = read_json(entry)?,
+            _ => {}
+        }
+    }
+
+    let manifest = records
+        .manifest
+        .as_ref()
+        .ok_or(anyhow!("{} is missing from the archive", MANIFEST))?;
+    if manifest.format != ARCHIVE_FORMAT {
+        bail!("not a {} file", ARCHIVE_FORMAT);
+    }
+    if manifest.version > ARCHIVE_VERSION {
+        bail!(
+            "archive version {} is newer than the supported version {}",
+            manifest.version,
+            ARCHIVE_VERSION
+        );
+    }
+    Ok(records)
+}
+
+fn remap(map: &HashMap<i64, i64>, ids: &[i64], kind: &str) -> anyhow::Result<Vec<i64>> {
+    ids.iter()
+        .map(|id| {
+            map.get(id)
+                .copied()
+                .ok_or(anyhow!("archive refers to unknown {} {}", kind, id))
+        })
+        .collect()
+}
+
+async fn import_records(
+    state: &AppState,
+    records: &ArchiveRecords,
+    summary: &mut ImportSummary,
+    tx: &mut Transaction<'_, Postgres>,
+) -> anyhow::Result<HashMap<String, PendingFile>> {
+    let mut group_ids: HashMap<i64, i64> = HashMap::new();
+    for group in &records.tag_groups {
+        let id = sqlx::query_scalar!(
+            r#"INSERT INTO tag_groups (name, description, created)
+               VALUES ($1, $2, $3)
+               ON CONFLICT (name) DO UPDATE SET description = COALESCE(tag_groups.description, EXCLUDED.description)
+               RETURNING id"#,
+            group.name,
+            group.description,
+            group.created
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+        group_ids.insert(group.id, id);
+    }
+
+    // Tags are matched by name or alias, an existing tag keeps its group
+    let mut tag_ids: HashMap<i64, i64> = HashMap::new();
+    for tag in &records.tags {
+        let group = *group_ids
+            .get(&tag.group)
+            .ok_or(anyhow!("archive refers to unknown tag group {}", tag.group))?;
+        let id = match sqlx::query_scalar!("SELECT tag FROM tag_alias WHERE alias = $1", tag.tag)
+            .fetch_optional(&mut **tx)
+            .await?
+        {
+            Some(id) => id,
+            None => {
+                sqlx::query_scalar!(
+                    r#"INSERT INTO tags (tag, "group", description, created)
+                       VALUES ($1, $2, $3, $4)
+                       ON CONFLICT (tag) DO UPDATE SET tag = EXCLUDED.tag
+                       RETURNING id"#,
+                    tag.tag,
+                    group,
+                    tag.description,
+                    tag.created
+                )
+                .fetch_one(&mut **tx)
+                .await?
+            }
+        };
+        // An existing tag keeps its own description
+        sqlx::query!(
+            "UPDATE tags SET description = $2 WHERE id = $1 AND description IS NULL",
+            id,
+            tag.description
+        )
+        .execute(&mut **tx)
+        .await?;
+        // Aliases already taken, or that are tags in their own right here, are left alone
+        sqlx::query!(
+            r#"INSERT INTO tag_alias (tag, alias)
+               SELECT $1, alias FROM UNNEST($2::text[]) AS alias
+               WHERE NOT EXISTS (SELECT 1 FROM tags WHERE tags.tag = alias)
+               ON CONFLICT DO NOTHING"#,
+            id,
+            &tag.aliases[..]
+        )
+        .execute(&mut **tx)
+        .await?;
+        tag_ids.insert(tag.id, id);
+    }
+
+    // Implications are added once every tag is known, skipping any that would lead back to their
+    // own tag through the implications already here
+    for tag in &records.tags {
+        let id = tag_ids[&tag.id];
+        for implies in remap(&tag_ids, &tag.implies, "tag")? {
+            let cycle = sqlx::query_scalar!(
+                r#"WITH RECURSIVE implied AS (SELECT $1::bigint AS id
+                                              UNION
+                                              SELECT tag_implications.implies
+                                              FROM tag_implications
+                                                       JOIN implied ON tag_implications.tag = implied.id)
+                   SELECT EXISTS (SELECT 1 FROM implied WHERE id = $2) AS "cycle!""#,
+                implies,
+                id
+            )
+            .fetch_one(&mut **tx)
+            .await?;
+            if cycle {
+                tracing::warn!(
+                    "skipping an implication of tag {}, it would lead back to itself",
+                    tag.tag
+                );
+                continue;
+            }
+            sqlx::query!(
+                "INSERT INTO tag_implications (tag, implies) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                id,
+                implies
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+    }
+    summary.tags = tag_ids.len();
+
+    // Creators are matched on any of their aliases
+    let mut creator_ids: HashMap<i64, i64> = HashMap::new();
+    for creator in &records.creators {
+        let mut aliases: Vec<String> = creator.aliases.iter().map(|a| a.to_lowercase()).collect();
+        aliases.push(creator.name.to_lowercase());
+        aliases.sort();
+        aliases.dedup();
+
+        let id = match sqlx::query_scalar!(
+            "SELECT creator FROM creator_alias WHERE alias = ANY ($1::varchar[]) LIMIT 1",
+            &aliases[..]
+        )
+        .fetch_optional(&mut **tx)
+        .await?
+        {
+            Some(id) => id,
+            None => {
+                sqlx::query_scalar!(
+                    r#"INSERT INTO creators (name, sites, created)
+                       VALUES ($1, $2, $3)
+                       ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+                       RETURNING id"#,
+                    creator.name,
+                    creator.sites,
+                    creator.created
+                )
+                .fetch_one(&mut **tx)
+                .await?
+            }
+        };
+        sqlx::query!(
+            r#"INSERT INTO creator_alias (creator, alias)
+               SELECT $1, * FROM UNNEST($2::text[])
+               ON CONFLICT DO NOTHING"#,
+            id,
+            &aliases[..]
+        )
+        .execute(&mut **tx)
+        .await?;
+        creator_ids.insert(creator.id, id);
+    }
+    summary.creators = creator_ids.len();
+
+    // Saved searches are matched by name, an existing one keeps its query
+    let mut saved_search_ids: HashMap<i64, i64> = HashMap::new();
+    for saved in &records.saved_searches {
+        let id = sqlx::query_scalar!(
+            r#"INSERT INTO saved_searches (name, query, sort, created, updated)
+               VALUES ($1, $2, $3, $4, $5)
+               ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+               RETURNING id"#,
+            saved.name,
+            saved.query,
+            saved.sort,
+            saved.created,
+            saved.updated
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+        saved_search_ids.insert(saved.id, id);
+    }
+
+    // Collections are matched by name. Parents have to exist before their children
+    let mut collection_ids: HashMap<i64, i64> = HashMap::new();
+    let mut remaining: Vec<&CollectionRecord> = records.collections.iter().collect();
+    while !remaining.is_empty() {
+        let (ready, waiting): (Vec<&CollectionRecord>, Vec<&CollectionRecord>) =
+            remaining.into_iter().partition(|c| match c.parent {
+                None => true,
+                Some(parent) => collection_ids.contains_key(&parent),
+            });
+        if ready.is_empty() {
+            bail!(
+                "collections {:?} have parents missing from the archive",
+                waiting.iter().map(|c| c.id).collect::<Vec<i64>>()
+            );
+        }
+        for collection in ready {
+            let parent = collection.parent.map(|p| collection_ids[&p]);
+            let saved_search = match collection.saved_search {
+                Some(saved_search) => Some(
+                    *saved_search_ids
+                        .get(&saved_search)
+                        .ok_or(anyhow!("archive refers to unknown saved search {}", saved_search))?,
+                ),
+                None => None,
+            };
+            // An existing collection only becomes smart if it has no media of its own
+            let id = sqlx::query_scalar!(
+                r#"INSERT INTO collections (name, description, created, parent, saved_search)
+                   VALUES ($1, $2, $3, $4, $5)
+                   ON CONFLICT (name) DO UPDATE SET
+                       description = COALESCE(collections.description, EXCLUDED.description),
+                       saved_search = CASE
+                           WHEN EXISTS (SELECT 1 FROM media_collection WHERE collection_id = collections.id)
+                               THEN collections.saved_search
+                           ELSE COALESCE(collections.saved_search, EXCLUDED.saved_search)
+                       END
+                   RETURNING id"#,
+                collection.name,
+                collection.description,
+                collection.created,
+                parent,
+                saved_search
+            )
+            .fetch_one(&mut **tx)
+            .await?;
+
+            sqlx::query!(
+                r#"INSERT INTO collection_creators (collection_id, creator_id)
+                   SELECT $1, * FROM UNNEST($2::bigint[])
+                   ON CONFLICT DO NOTHING"#,
+                id,
+                &remap(&creator_ids, &collection.creators, "creator")?[..]
+            )
+            .execute(&mut **tx)
+            .await?;
+            sqlx::query!(
+                r#"INSERT INTO collection_tags (collection_id, tag_id)
+                   SELECT $1, * FROM UNNEST($2::bigint[])
+                   ON CONFLICT DO NOTHING"#,
+                id,
+                &remap(&tag_ids, &collection.tags, "tag")?[..]
+            )
+            .execute(&mut **tx)
+            .await?;
+            collection_ids.insert(collection.id, id);
+        }
+        remaining = waiting;
+    }
+    summary.collections = collection_ids.len();
+
+    // Media already on the server, by file hash, have the archive's metadata merged in
+    let mut pending: HashMap<String, PendingFile> = HashMap::new();
+    let mut merged: Vec<i64> = Vec::new();
+    for item in &records.media {
+        let id = match sqlx::query_scalar!("SELECT id FROM media WHERE sha256 = $1", item.sha256)
+            .fetch_optional(&mut **tx)
+            .await?
+        {
+            Some(id) => {
+                merged.push(id);
+                id
+            }
+            None => {
+                let storage_uri = storage_name(&item.sha256, &item.storage_uri)?;
+                let id = sqlx::query_scalar!(
+                    r#"INSERT INTO media(storage_uri, sha256, uploaded, created, title, description, type, perceptual_hash, metadata)
+                       VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                       RETURNING id"#,
+                    storage_uri,
+                    item.sha256,
+                    item.uploaded,
+                    item.created,
+                    item.title,
+                    item.description,
+                    item.file_type,
+                    BitVec::from_bytes(&item.perceptual_hash.to_be_bytes()),
+                    item.metadata
+                )
+                .fetch_one(&mut **tx)
+                .await?;
+                pending.insert(
+                    item.file.clone(),
+                    PendingFile {
+                        sha256: item.sha256.clone(),
+                        storage_uri,
+                    },
+                );
+                id
+            }
+        };
+        summary.media_ids.push(id);
+
+        sqlx::query!(
+            r#"INSERT INTO media_creators (media_id, creator_id)
+               SELECT $1, * FROM UNNEST($2::bigint[])
+               ON CONFLICT DO NOTHING"#,
+            id,
+            &remap(&creator_ids, &item.creators, "creator")?[..]
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        sqlx::query!(
+            r#"INSERT INTO media_tags (media_id, tag_id)
+               SELECT $1, * FROM UNNEST($2::bigint[])
+               ON CONFLICT DO NOTHING"#,
+            id,
+            &remap(&tag_ids, &item.tags, "tag")?[..]
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        let collections = remap(
+            &collection_ids,
+            &item.collections.iter().map(|c| c.id).collect::<Vec<i64>>(),
+            "collection",
+        )?;
+        sqlx::query!(
+            r#"INSERT INTO media_collection (media_id, collection_id, ord)
+               SELECT $1, * FROM UNNEST($2::bigint[], $3::int[])
+               ON CONFLICT DO NOTHING"#,
+            id,
+            &collections[..],
+            &item.collections.iter().map(|c| c.ord).collect::<Vec<Option<i32>>>()[..] as _
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        let sources: Vec<_> = item
+            .sources
+            .iter()
+            .map(|s| (s, state.canonicaliser.canonicalise(&s.url)))
+            .collect();
+        sqlx::query!(
+            r#"INSERT INTO sources (media_id, source, type, source_title, site, post_id, added)
+               SELECT $1, u.source, u.type::source_type, u.title, u.site, u.post_id, u.added
+               FROM UNNEST($2::text[], $3::text[], $4::text[], $5::text[], $6::text[], $7::timestamptz[])
+                   AS u(source, type, title, site, post_id, added)
+               ON CONFLICT (media_id, source) DO NOTHING"#,
+            id,
+            &sources.iter().map(|(_, c)| c.url.clone()).collect::<Vec<String>>()[..],
+            &sources.iter().map(|(s, _)| s.source_type.clone()).collect::<Vec<Option<String>>>()[..] as _,
+            &sources.iter().map(|(s, _)| s.title.clone()).collect::<Vec<Option<String>>>()[..] as _,
+            &sources.iter().map(|(_, c)| c.site.clone()).collect::<Vec<Option<String>>>()[..] as _,
+            &sources.iter().map(|(_, c)| c.post_id.clone()).collect::<Vec<Option<String>>>()[..] as _,
+            &sources.iter().map(|(s, _)| s.added).collect::<Vec<chrono::DateTime<chrono::Utc>>>()[..]
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    sqlx::query!(
+        "UPDATE media SET version = version + 1 WHERE id = ANY ($1::bigint[])",
+        &merged[..]
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    summary.media_added = pending.len();
+    summary.media_merged = merged.len();
+    Ok(pending)
+}
+
+/// Copy the files of newly added media into storage, and make their thumbnails
+fn store_files(
+    state: &AppState,
+    path: &Path,
+    mut pending: HashMap<String, PendingFile>,
+    written: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        let Some(file) = pending.remove(&name) else {
+            continue;
+        };
+
+        let mut contents: Vec<u8> = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        if sha256(&contents) != file.sha256 {
+            bail!("{} does not match its sha256 {}", name, file.sha256);
+        }
+
+        let media_path = state.storage_dir.join(&file.storage_uri);
+        if !media_path.exists() {
+            std::fs::write(&media_path, &contents)?;
+            written.push(media_path);
+        }
+
+        let mut thumbnail_path = state.thumbnail_dir.join(&file.sha256);
+        thumbnail_path.set_extension("webp");
+        if !thumbnail_path.exists() {
+            let im = image::load_from_memory(&contents)?;
+            im.resize(400, 400, Lanczos3).save(&thumbnail_path)?;
+            written.push(thumbnail_path);
+        }
+    }
+
+    if let Some(missing) = pending.keys().next() {
+        bail!("{} is missing from the archive", missing);
+    }
+    Ok(())
+}
+
+/// Restore an archive into the library. Everything is remapped onto the ids of the target, and
+/// media already present are matched by file hash rather than added again
+pub async fn import_archive(state: &AppState, path: &Path) -> anyhow::Result<ImportSummary> {
+    let records = read_records(path)?;
+    let mut summary = ImportSummary::default();
+
+    let mut tx = state.conn.begin().await?;
+    let pending = import_records(state, &records, &mut summary, &mut tx).await?;
+
+    let mut written: Vec<PathBuf> = Vec::new();
+    let result = match store_files(state, path, pending, &mut written) {
+        Ok(_) => tx.commit().await.map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        //If the import fails, remove the files
+        for file in written {
+            std::fs::remove_file(file).ok();
+        }
+        return Err(e);
+    }
+    Ok(summary)
+}
@@ -0,0 +1,176 @@
+//! Portable archives of the library.
+//!
+//! An archive is a tar file holding `manifest.json`, one JSON file per kind of record, and the
+//! original media files under `files/`. Records keep the ids they had on the exporting server, and
+//! refer to each other by those ids; an import maps them onto whatever ids the target uses.
+
+pub(crate) mod export;
+pub(crate) mod import;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub const ARCHIVE_FORMAT: &str = "dragonhorde-archive";
+pub const ARCHIVE_VERSION: u32 = 2;
+
+pub const MANIFEST: &str = "manifest.json";
+pub const TAG_GROUPS: &str = "tag_groups.json";
+pub const TAGS: &str = "tags.json";
+pub const CREATORS: &str = "creators.json";
+pub const COLLECTIONS: &str = "collections.json";
+pub const SAVED_SEARCHES: &str = "saved_searches.json";
+pub const MEDIA: &str = "media.json";
+pub const FILES_DIR: &str = "files";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: String,
+    pub version: u32,
+    pub exported: DateTime<Utc>,
+    pub media: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagGroupRecord {
+    pub id: i64,
+    pub name: String,
+    pub description: Option<String>,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagRecord {
+    pub id: i64,
+    pub tag: String,
+    /// Id of the tag group
+    pub group: i64,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub created: DateTime<Utc>,
+    /// Other names that stand for the tag
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Ids of the tags it implies
+    #[serde(default)]
+    pub implies: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatorRecord {
+    pub id: i64,
+    pub name: String,
+    pub sites: Option<String>,
+    pub created: DateTime<Utc>,
+    pub aliases: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CollectionRecord {
+    pub id: i64,
+    pub name: String,
+    pub description: Option<String>,
+    pub created: DateTime<Utc>,
+    /// Id of the parent collection
+    pub parent: Option<i64>,
+    /// Id of the saved search a smart collection gets its media from
+    #[serde(default)]
+    pub saved_search: Option<i64>,
+    pub creators: Vec<i64>,
+    pub tags: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedSearchRecord {
+    pub id: i64,
+    pub name: String,
+    pub query: serde_json::Value,
+    pub sort: serde_json::Value,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MediaCollectionRecord {
+    pub id: i64,
+    pub ord: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SourceRecord {
+    pub url: String,
+    #[serde(rename = "type")]
+    pub source_type: Option<String>,
+    pub title: Option<String>,
+    pub site: Option<String>,
+    pub post_id: Option<String>,
+    pub added: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MediaRecord {
+    pub id: i64,
+    /// Path of the file within the archive
+    pub file: String,
+    pub storage_uri: String,
+    pub sha256: String,
+    pub file_type: Option<String>,
+    pub perceptual_hash: i64,
+    pub uploaded: DateTime<Utc>,
+    pub created: Option<DateTime<Utc>>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub metadata: Option<serde_json::Value>,
+    pub creators: Vec<i64>,
+    pub tags: Vec<i64>,
+    pub collections: Vec<MediaCollectionRecord>,
+    pub sources: Vec<SourceRecord>,
+}
+
+/// Which media to export. Every filter given must match; with none, the whole library is exported
+#[derive(Debug, Default)]
+pub struct ExportFilter {
+    /// Collection path, eg. comics/series
+    pub collection: Option<String>,
+    pub tags: Vec<String>,
+    pub creators: Vec<String>,
+}
+
+impl ExportFilter {
+    pub fn is_empty(&self) -> bool {
+        self.collection.is_none() && self.tags.is_empty() && self.creators.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_records_keep_their_aliases_and_implications() {
+        let record = TagRecord {
+            id: 1,
+            tag: "wyvern".to_string(),
+            group: 2,
+            description: Some("two legged dragon".to_string()),
+            created: Utc::now(),
+            aliases: vec!["wyverns".to_string()],
+            implies: vec![3, 4],
+        };
+        let json = serde_json::to_string(&record).unwrap();
+        let read: TagRecord = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.description, record.description);
+        assert_eq!(read.aliases, record.aliases);
+        assert_eq!(read.implies, record.implies);
+    }
+
+    #[test]
+    fn tag_records_from_older_archives_read() {
+        let read: TagRecord = serde_json::from_str(
+            r#"{"id": 1, "tag": "wyvern", "group": 2, "created": "2024-06-01T00:00:00Z"}"#,
+        )
+        .unwrap();
+        assert_eq!(read.description, None);
+        assert!(read.aliases.is_empty());
+        assert!(read.implies.is_empty());
+    }
+}
@@ -0,0 +1,209 @@
+//! Filling in metadata for media stored before it was recorded, and bringing stored data in line
+//! with rules added since.
+
+use crate::api_models::SourceType;
+use crate::AppState;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Record the size of every stored file that has no size in its metadata. Files missing from
+/// storage are skipped with a warning
+pub async fn file_sizes(state: &AppState) -> anyhow::Result<usize> {
+    let rows = sqlx::query!(
+        r#"SELECT id, storage_uri
+           FROM media
+           WHERE metadata IS NULL OR NOT metadata ? 'file_size'
+           ORDER BY id"#
+    )
+    .fetch_all(&state.conn)
+    .await?;
+
+    let mut count = 0;
+    for row in rows {
+        let size = match std::fs::metadata(state.storage_dir.join(&row.storage_uri)) {
+            Ok(meta) => meta.len(),
+            Err(e) => {
+                tracing::warn!("can't read file for media {}: {e}", row.id);
+                continue;
+            }
+        };
+        sqlx::query!(
+            r#"UPDATE media
+               SET metadata = COALESCE(metadata, '{}'::jsonb) || JSONB_BUILD_OBJECT('file_size', $2::bigint)
+               WHERE id = $1"#,
+            row.id,
+            size.cast_signed()
+        )
+        .execute(&state.conn)
+        .await?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Add the tags implied by the tags media already have, for every item or only those tagged with
+/// `tag`, bumping the version of every item given new tags. Returns their ids, for their sidecars
+/// to be rewritten
+pub async fn tag_implications(tag: Option<i64>, db: &sqlx::PgPool) -> anyhow::Result<Vec<i64>> {
+    let mut tx = db.begin().await?;
+    let mut changed = sqlx::query_scalar!(
+        r#"WITH RECURSIVE implied AS (SELECT media_id, tag_id
+                                      FROM media_tags
+                                      WHERE $1::bigint IS NULL OR tag_id = $1
+                                      UNION
+                                      SELECT implied.media_id, tag_implications.implies
+                                      FROM implied
+                                               JOIN tag_implications ON tag_implications.tag = implied.tag_id)
+           INSERT INTO media_tags(media_id, tag_id)
+           SELECT media_id, tag_id FROM implied
+           ON CONFLICT DO NOTHING
+           RETURNING media_id"#,
+        tag
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    changed.sort();
+    changed.dedup();
+    sqlx::query!(
+        "UPDATE media SET version = version + 1 WHERE id = ANY ($1::bigint[])",
+        &changed[..]
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(changed)
+}
+
+/// What rewriting the stored sources changed
+pub struct CanonicalSources {
+    /// Sources rewritten to their canonical url
+    pub updated: u64,
+    /// Duplicate sources removed after being merged into another
+    pub merged: u64,
+}
+
+struct SourceRow {
+    id: i64,
+    source: String,
+    source_type: Option<SourceType>,
+    title: Option<String>,
+    site: Option<String>,
+    post_id: Option<String>,
+}
+
+/// Rewrite every stored source to its canonical url, merging sources on the same media item
+/// that turn out to be the same post. Safe to run again after adding new site rules.
+pub async fn canonical_sources(state: &AppState) -> anyhow::Result<CanonicalSources> {
+    let mut tx = state.conn.begin().await?;
+
+    let rows = sqlx::query!(
+        r#"SELECT id, media_id, source, type::text AS "source_type", source_title, site, post_id
+           FROM sources
+           ORDER BY id
+           FOR UPDATE"#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    // Group by media item and canonical url, lowest id first so it is the one that's kept
+    let mut groups: HashMap<(i64, String), Vec<SourceRow>> = HashMap::new();
+    for row in rows {
+        let canonical = state.canonicaliser.canonicalise(&row.source);
+        groups
+            .entry((row.media_id, canonical.url))
+            .or_default()
+            .push(SourceRow {
+                id: row.id,
+                source: row.source,
+                source_type: row.source_type.and_then(|t| SourceType::from_str(&t).ok()),
+                title: row.source_title,
+                site: row.site,
+                post_id: row.post_id,
+            });
+    }
+
+    let mut to_delete: Vec<i64> = Vec::new();
+    let mut ids: Vec<i64> = Vec::new();
+    let mut urls: Vec<String> = Vec::new();
+    let mut types: Vec<Option<String>> = Vec::new();
+    let mut titles: Vec<Option<String>> = Vec::new();
+    let mut sites: Vec<Option<String>> = Vec::new();
+    let mut post_ids: Vec<Option<String>> = Vec::new();
+    let mut media_ids: Vec<i64> = Vec::new();
+
+    for ((media_id, url), group) in groups {
+        let canonical = state.canonicaliser.canonicalise(&url);
+        let keep = &group[0];
+        let source_type = group
+            .iter()
+            .filter_map(|r| r.source_type.clone())
+            .max_by_key(|t| t.rank());
+        let title = group.iter().find_map(|r| r.title.clone());
+
+        if group.len() == 1
+            && keep.source == canonical.url
+            && keep.site == canonical.site
+            && keep.post_id == canonical.post_id
+        {
+            continue;
+        }
+
+        to_delete.extend(group[1..].iter().map(|r| r.id));
+        media_ids.push(media_id);
+        ids.push(keep.id);
+        urls.push(canonical.url);
+        types.push(source_type.map(|t| t.as_str().to_string()));
+        titles.push(title);
+        sites.push(canonical.site);
+        post_ids.push(canonical.post_id);
+    }
+
+    // Duplicates go first, so the rewritten urls don't collide with them
+    sqlx::query!(
+        r#"DELETE FROM sources WHERE id = ANY($1::bigint[])"#,
+        &to_delete[..]
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE sources
+        SET source       = u.source,
+            type         = u.type::source_type,
+            source_title = u.title,
+            site         = u.site,
+            post_id      = u.post_id
+        FROM UNNEST($1::bigint[], $2::text[], $3::text[], $4::text[], $5::text[], $6::text[])
+            AS u(id, source, type, title, site, post_id)
+        WHERE sources.id = u.id"#,
+        &ids[..],
+        &urls[..],
+        &types[..] as &[Option<String>],
+        &titles[..] as &[Option<String>],
+        &sites[..] as &[Option<String>],
+        &post_ids[..] as &[Option<String>]
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    media_ids.sort();
+    media_ids.dedup();
+    sqlx::query!(
+        "UPDATE media SET version = version + 1 WHERE id = ANY ($1::bigint[])",
+        &media_ids[..]
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    state
+        .sidecars
+        .sync(&state.storage_dir, &media_ids, &state.conn)
+        .await?;
+
+    Ok(CanonicalSources {
+        updated: ids.len() as u64,
+        merged: to_delete.len() as u64,
+    })
+}
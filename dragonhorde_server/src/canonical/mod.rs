@@ -0,0 +1,188 @@
+//! Canonical forms for source urls, so the same post is only stored once no matter which of a
+//! site's url styles it was given in.
+use url::Url;
+
+pub mod sites;
+
+/// Query parameters that only track where a link was clicked, and never identify a post
+const TRACKING_PARAMS: &[&str] = &[
+    "fbclid", "gclid", "dclid", "msclkid", "igshid", "mc_cid", "mc_eid", "ref", "ref_src",
+    "ref_url", "si",
+];
+
+/// A source url after canonicalisation
+#[derive(Clone, Debug, PartialEq)]
+pub struct CanonicalSource {
+    pub url: String,
+    /// Host the source points to, without any leading www.
+    pub site: Option<String>,
+    /// Id of the post on the site, when a site rule knows where to find it
+    pub post_id: Option<String>,
+}
+
+/// Rewrites the urls of a single site into one canonical form.
+///
+/// Rules run after the generic clean up, so they see an https url with no `www.`, fragment,
+/// tracking parameters or trailing slash. Applying a rule to its own output must not change it.
+pub trait SiteRule: Send + Sync {
+    /// Hosts, without `www.`, that this rule handles
+    fn hosts(&self) -> &[&'static str];
+
+    /// Rewrite the url in place, returning the id of the post it points to if there is one
+    fn canonicalise(&self, url: &mut Url) -> Option<String>;
+}
+
+pub struct Canonicaliser {
+    rules: Vec<Box<dyn SiteRule>>,
+}
+
+impl Canonicaliser {
+    /// A canonicaliser with only the generic clean up and no site rules
+    pub fn empty() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    /// Add a site rule. Rules added later take priority over earlier ones for the same host
+    pub fn with_rule(mut self, rule: impl SiteRule + 'static) -> Self {
+        self.rules.insert(0, Box::new(rule));
+        self
+    }
+
+    pub fn canonicalise(&self, source: &str) -> CanonicalSource {
+        let source = source.trim();
+        let mut url = match Url::parse(source) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url,
+            // Not a web link (eg file://), nothing to normalise
+            _ => {
+                return CanonicalSource {
+                    url: source.to_string(),
+                    site: None,
+                    post_id: None,
+                };
+            }
+        };
+
+        url.set_scheme("https").ok();
+        if let Some(stripped) = url
+            .host_str()
+            .and_then(|h| h.strip_prefix("www."))
+            .map(|h| h.to_string())
+        {
+            url.set_host(Some(&stripped)).ok();
+        }
+        url.set_fragment(None);
+
+        let query: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(k, _)| !k.starts_with("utm_") && !TRACKING_PARAMS.contains(&k.as_ref()))
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        if query.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(query);
+        }
+
+        let path = url.path().to_string();
+        if path.len() > 1 && path.ends_with('/') {
+            url.set_path(path.trim_end_matches('/'));
+        }
+
+        let host = url.host_str().unwrap_or_default().to_string();
+        let post_id = self
+            .rules
+            .iter()
+            .find(|r| r.hosts().contains(&host.as_str()))
+            .and_then(|r| r.canonicalise(&mut url));
+
+        CanonicalSource {
+            site: url.host_str().map(|h| h.to_string()),
+            url: url.to_string(),
+            post_id,
+        }
+    }
+}
+
+impl Default for Canonicaliser {
+    /// Canonicaliser with rules for every site the importer knows about
+    fn default() -> Self {
+        Self::empty()
+            .with_rule(sites::Twitter)
+            .with_rule(sites::Weasyl)
+            .with_rule(sites::E621)
+            .with_rule(sites::Furaffinity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canonical(source: &str) -> CanonicalSource {
+        Canonicaliser::default().canonicalise(source)
+    }
+
+    #[test]
+    fn furaffinity_full_becomes_view() {
+        let source = canonical("http://www.furaffinity.net/full/12345/");
+        assert_eq!(source.url, "https://furaffinity.net/view/12345/");
+        assert_eq!(source.site.as_deref(), Some("furaffinity.net"));
+        assert_eq!(source.post_id.as_deref(), Some("12345"));
+    }
+
+    #[test]
+    fn e621_post_show_becomes_posts() {
+        let source = canonical("https://e926.net/post/show/678?q=dragon#comments");
+        assert_eq!(source.url, "https://e621.net/posts/678");
+        assert_eq!(source.site.as_deref(), Some("e621.net"));
+        assert_eq!(source.post_id.as_deref(), Some("678"));
+    }
+
+    #[test]
+    fn weasyl_user_submission_becomes_submission() {
+        let source = canonical("https://www.weasyl.com/~someone/submissions/42/some-title");
+        assert_eq!(source.url, "https://weasyl.com/submission/42");
+        assert_eq!(source.post_id.as_deref(), Some("42"));
+    }
+
+    #[test]
+    fn twitter_becomes_x() {
+        let source = canonical("https://mobile.twitter.com/someone/status/1001?s=20");
+        assert_eq!(source.url, "https://x.com/someone/status/1001");
+        assert_eq!(source.post_id.as_deref(), Some("1001"));
+    }
+
+    #[test]
+    fn tracking_parameters_are_dropped() {
+        let source = canonical("https://example.com/art/?utm_source=feed&page=2&fbclid=abc");
+        assert_eq!(source.url, "https://example.com/art?page=2");
+        assert_eq!(source.site.as_deref(), Some("example.com"));
+        assert_eq!(source.post_id, None);
+    }
+
+    #[test]
+    fn non_web_sources_are_left_alone() {
+        let source = canonical(" file:///home/someone/art.png ");
+        assert_eq!(source.url, "file:///home/someone/art.png");
+        assert_eq!(source.site, None);
+    }
+
+    #[test]
+    fn canonicalising_twice_changes_nothing() {
+        let canonicaliser = Canonicaliser::default();
+        for source in [
+            "http://www.furaffinity.net/full/12345/",
+            "https://www.furaffinity.net/view/12345",
+            "https://e926.net/post/show/678?q=dragon",
+            "https://e621.net/posts/678/",
+            "https://www.weasyl.com/~someone/submissions/42/some-title",
+            "https://weasyl.com/view/42",
+            "https://fxtwitter.com/someone/status/1001/photo/1",
+            "https://example.com/art/?utm_source=feed&page=2#top",
+            "https://example.com/",
+        ] {
+            let once = canonicaliser.canonicalise(source);
+            assert_eq!(canonicaliser.canonicalise(&once.url), once, "{}", source);
+        }
+    }
+}
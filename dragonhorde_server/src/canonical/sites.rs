@@ -0,0 +1,107 @@
+use crate::canonical::SiteRule;
+use url::Url;
+
+/// Path segments of a url, ignoring empty ones
+fn segments(url: &Url) -> Vec<String> {
+    url.path_segments()
+        .map(|s| s.filter(|i| !i.is_empty()).map(|i| i.to_string()).collect())
+        .unwrap_or_default()
+}
+
+fn numeric(id: &str) -> Option<String> {
+    if !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()) {
+        Some(id.to_string())
+    } else {
+        None
+    }
+}
+
+/// `/view/{id}` and `/full/{id}` → `https://furaffinity.net/view/{id}/`
+pub struct Furaffinity;
+
+impl SiteRule for Furaffinity {
+    fn hosts(&self) -> &[&'static str] {
+        &["furaffinity.net"]
+    }
+
+    fn canonicalise(&self, url: &mut Url) -> Option<String> {
+        let id = match &segments(url)[..] {
+            [kind, id, ..] if kind == "view" || kind == "full" => numeric(id)?,
+            _ => return None,
+        };
+        url.set_path(&format!("/view/{}/", id));
+        url.set_query(None);
+        Some(id)
+    }
+}
+
+/// `/posts/{id}` and `/post/show/{id}` → `https://e621.net/posts/{id}`
+pub struct E621;
+
+impl SiteRule for E621 {
+    fn hosts(&self) -> &[&'static str] {
+        &["e621.net", "e926.net"]
+    }
+
+    fn canonicalise(&self, url: &mut Url) -> Option<String> {
+        let id = match &segments(url)[..] {
+            [posts, id, ..] if posts == "posts" => numeric(id)?,
+            [post, show, id, ..] if post == "post" && show == "show" => numeric(id)?,
+            _ => return None,
+        };
+        url.set_host(Some("e621.net")).ok();
+        url.set_path(&format!("/posts/{}", id));
+        url.set_query(None);
+        Some(id)
+    }
+}
+
+/// `/~{user}/submissions/{id}/{slug}`, `/submission/{id}` and `/view/{id}` →
+/// `https://weasyl.com/submission/{id}`
+pub struct Weasyl;
+
+impl SiteRule for Weasyl {
+    fn hosts(&self) -> &[&'static str] {
+        &["weasyl.com"]
+    }
+
+    fn canonicalise(&self, url: &mut Url) -> Option<String> {
+        let id = match &segments(url)[..] {
+            [user, submissions, id, ..] if user.starts_with('~') && submissions == "submissions" => {
+                numeric(id)?
+            }
+            [kind, id, ..] if kind == "submission" || kind == "view" => numeric(id)?,
+            _ => return None,
+        };
+        url.set_path(&format!("/submission/{}", id));
+        url.set_query(None);
+        Some(id)
+    }
+}
+
+/// Twitter, x.com and the embed fixers → `https://x.com/{user}/status/{id}`
+pub struct Twitter;
+
+impl SiteRule for Twitter {
+    fn hosts(&self) -> &[&'static str] {
+        &[
+            "x.com",
+            "twitter.com",
+            "mobile.twitter.com",
+            "fxtwitter.com",
+            "vxtwitter.com",
+            "fixupx.com",
+        ]
+    }
+
+    fn canonicalise(&self, url: &mut Url) -> Option<String> {
+        let (user, id) = match &segments(url)[..] {
+            [user, status, id, ..] if status == "status" => (user.clone(), numeric(id)?),
+            _ => return None,
+        };
+        url.set_host(Some("x.com")).ok();
+        url.set_path(&format!("/{}/status/{}", user, id));
+        url.set_query(None);
+        Some(id)
+    }
+}
@@ -1,4 +1,5 @@
 use crate::error::AppError;
+use crate::query::Pattern;
 use crate::AppState;
 use axum::extract::State;
 use axum::http::StatusCode;
@@ -33,89 +34,103 @@ impl Default for TagType {
 
 #[derive(IntoParams, Debug, Deserialize)]
 pub struct TagQuery {
+    /// What has been typed so far, optionally with a namespace, eg. `species:dra` or `creator:foo`
     tag: String,
     #[param(inline)]
     #[serde(default)]
-    tag_type: TagType
+    tag_type: TagType,
+    /// Most suggestions to return, 20 unless given
+    limit: Option<i64>,
+    /// Suggestions to skip, for further pages
+    #[serde(default)]
+    offset: i64,
 }
 
+#[serde_with::skip_serializing_none]
 #[derive(utoipa::ToSchema, Debug, Deserialize, Serialize)]
 pub struct TagReturn {
     id: i64,
     tag: String,
     #[schema(inline, example=TagType::Tag)]
     tag_type: TagType,
-    tag_group: Option<String>
+    tag_group: Option<String>,
+    /// For creators and tags found by one of their aliases, the alias that matched
+    alias: Option<String>,
 }
 
-#[utoipa::path(get, path = "/v1/autocomplete", params(TagQuery), responses((status = OK, body = Vec<TagReturn>)), tags = ["tags"])]
-
+const DEFAULT_LIMIT: i64 = 20;
 
+/// Suggest tags, creators and collections for partly typed input. Matching ignores case and
+/// tolerates typos, finding names that contain the input or are close to it, and creators and
+/// tags by any of their aliases. Prefix matches come first, then closer matches, with often used
+/// entries ranked higher.
+#[utoipa::path(get, path = "/v1/autocomplete", params(TagQuery), responses((status = OK, body = Vec<TagReturn>)), tags = ["tags"])]
 pub async fn autocomplete(
     state: State<AppState>,
     query: Query<TagQuery>,
 ) -> Result<(StatusCode, Json<Vec<TagReturn>>), AppError> {
     dbg!(&query);
-    let mut combined : Vec<(TagReturn, i64)> = Vec::new();
-
-    let tag = match query.tag.split_once(":") {
-        Some(t) => {t.1.to_string()},
-        None => query.tag.as_str().to_string(),
+    let (neg, input) = match query.tag.strip_prefix("-") {
+        Some(input) => (true, input),
+        None => (false, query.tag.as_str()),
     };
+    // A namespace narrows the suggestions to creators, collections or a tag group
+    let (namespace, search) = match input.split_once(":") {
+        Some((namespace, search)) => (Some(namespace.to_lowercase()), search.to_lowercase()),
+        None => (None, input.to_lowercase()),
+    };
+    let wanted = |tag_type: TagType, namespaces: &[&str]| {
+        (query.tag_type == TagType::All || query.tag_type == tag_type)
+            && match &namespace {
+                None => true,
+                Some(namespace) => namespaces.contains(&namespace.as_str()),
+            }
+    };
+    let creators = wanted(TagType::Artist, &["creator", "artist"]);
+    let collections = wanted(TagType::Collection, &["collection"]);
+    let group = namespace
+        .as_deref()
+        .filter(|n| !["creator", "artist", "collection", "tag"].contains(n));
+    let tags = (query.tag_type == TagType::All || query.tag_type == TagType::Tag)
+        && namespace.as_deref().is_none_or(|n| n == "tag" || group.is_some());
 
-    if query.tag_type == TagType::All || query.tag_type == TagType::Artist {
-        let creators: Vec<(String, i64)> = sqlx::query!("SELECT creators.name, count(media_creators.media_id) as count from creators
-           LEFT JOIN media_creators ON creators.id = media_creators.creator_id
-           WHERE name like $1
-           GROUP BY creators.name
-            ORDER BY count(media_creators.media_id)
-                   ", format!("{}%", tag))
-            .fetch_all(&state.conn)
-            .await?
-            .into_iter().map(|i| (i.name, i.count.unwrap())).collect();
-        combined.extend(creators.into_iter().map(|(creator, count)| (TagReturn{id: 0, tag: creator, tag_type: TagType::Artist, tag_group: None}, count)));
-    }
-
-    if query.tag_type == TagType::All || query.tag_type == TagType::Collection {
-        let collections: Vec<(i64, String, i64)> = sqlx::query!("SELECT collections.id, collections.name, count(media_collection.media_id) as count from collections
-           LEFT JOIN media_collection ON collections.id = media_collection.collection_id
-           WHERE name like $1
-           GROUP BY collections.id, collections.name
-            ORDER BY count(media_collection.media_id)
-                   ", format!("{}%", tag))
-            .fetch_all(&state.conn)
-            .await?
-            .into_iter().map(|i| (i.id, i.name, i.count.unwrap())).collect();
-        combined.extend(collections.into_iter().map(|(id, collection, count)| (TagReturn{id, tag: collection, tag_type: TagType::Collection, tag_group: None}, count)));
-    }
-
-    if query.tag_type == TagType::All || query.tag_type == TagType::Tag {
-        let mut search = tag.to_string();
-        let mut neg = false;
-        if search.starts_with("-"){
-            search = search.replacen("-", "", 1);
-            neg = true;
-        }
-        let tags: Vec<(String, String, i64)> = sqlx::query!("SELECT tag, name, count(media_tags.media_id) as count from tags
-           LEFT JOIN media_tags on tags.id = media_tags.tag_id
-           LEFT JOIN tag_groups ON tags.group = tag_groups.id
-           WHERE tag like $1
-           GROUP BY tags.tag, tag_groups.name
-            ORDER BY count(media_tags.media_id)
-                   ", format!("{}%", query.tag.as_str()))
-            .fetch_all(&state.conn)
-            .await?
-            .into_iter().map(|i| (i.tag, i.name, i.count.unwrap())).collect();
-        combined.extend(tags.into_iter().map(|(t,g, count)| (TagReturn{id:0, tag: if neg {format!("-{}", t)} else {t}, tag_type:TagType::Tag, tag_group: Some(g)}, count)));
-    }
-
-
-
-    combined.sort_by_key(|x| x.1);
-    combined.reverse();
-    // dbg!(&combined);
-    Ok((
-        StatusCode::OK,
-        Json(combined.into_iter().map(|x| x.0).collect()),
-    ))
-}
\ No newline at end of file
+    let found = sqlx::query_file!(
+        "sql/endpoints/autocomplete/autocomplete.sqlx",
+        search,
+        Pattern(search.clone()).like(),
+        creators,
+        collections,
+        tags,
+        group,
+        query.limit.unwrap_or(DEFAULT_LIMIT),
+        query.offset
+    )
+    .fetch_all(&state.conn)
+    .await?
+    .into_iter()
+    .map(|i| match i.kind.as_str() {
+        "creator" => TagReturn {
+            id: i.id,
+            tag: i.value,
+            tag_type: TagType::Artist,
+            tag_group: None,
+            alias: i.alias,
+        },
+        "collection" => TagReturn {
+            id: i.id,
+            tag: i.value,
+            tag_type: TagType::Collection,
+            tag_group: None,
+            alias: None,
+        },
+        _ => TagReturn {
+            id: i.id,
+            tag: if neg { format!("-{}", i.value) } else { i.value },
+            tag_type: TagType::Tag,
+            tag_group: i.tag_group,
+            alias: i.alias,
+        },
+    })
+    .collect();
+    Ok((StatusCode::OK, Json(found)))
+}
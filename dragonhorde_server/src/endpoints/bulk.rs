@@ -0,0 +1,111 @@
+use crate::api_models::{
+    ApiBulkEdit, ApiBulkEditResult, ApiMediaPatch, ListPatch, Sort, TagGroupsPatch,
+};
+use crate::endpoints::media::media_patch_relations;
+use crate::endpoints::search::{expand_smart_collections, json_query_expr};
+use crate::error::AppError;
+use crate::error::AppError::{BadRequest, NotFound};
+use crate::query;
+use crate::AppState;
+use axum::extract::State;
+use axum::Json;
+use std::collections::HashMap;
+
+/// Add and remove creators, sources, collections and tags on many media items at once, selected
+/// either by id or by a search. Every item is changed in a single transaction.
+#[utoipa::path(post, path = "/v1/media/bulk", request_body = ApiBulkEdit, responses((status = OK, body = ApiBulkEditResult)), tags = ["media"])]
+pub async fn media_bulk_edit(
+    state: State<AppState>,
+    Json(payload): Json<ApiBulkEdit>,
+) -> Result<Json<ApiBulkEditResult>, AppError> {
+    let mut ids = match (&payload.ids, &payload.query) {
+        (Some(ids), None) => ids.clone(),
+        (None, Some(query)) => {
+            query::search_ids(
+                &expand_smart_collections(&json_query_expr(query)?, &state.conn).await?,
+                &Sort::default(),
+                i64::MAX,
+                0,
+                &state.conn,
+            )
+            .await?
+        }
+        _ => {
+            return Err(BadRequest(
+                "exactly one of ids or query must be given".to_string(),
+            ))
+        }
+    };
+    ids.sort();
+    ids.dedup();
+
+    let patch = ApiMediaPatch {
+        created: None,
+        title: None,
+        description: None,
+        creators: payload.creators.clone().map(ListPatch::Operations),
+        sources: payload.sources.clone().map(ListPatch::Operations),
+        collections: payload.collections.clone().map(ListPatch::Operations),
+        tag_groups: payload.tag_groups.clone().map(TagGroupsPatch::Operations),
+        version: None,
+    };
+
+    let mut tx = state.conn.begin().await?;
+
+    let before: HashMap<i64, String> = sqlx::query_file!(
+        "sql/endpoints/media/media_relations_state.sqlx",
+        &ids[..]
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|r| (r.id, r.state))
+    .collect();
+
+    if payload.ids.is_some() && before.len() != ids.len() {
+        let missing: Vec<String> = ids
+            .iter()
+            .filter(|id| !before.contains_key(id))
+            .map(|id| id.to_string())
+            .collect();
+        return Err(NotFound(format!("media {} not found", missing.join(", "))));
+    }
+
+    for id in &ids {
+        media_patch_relations(&patch, *id, &state.canonicaliser, &mut tx).await?;
+    }
+
+    let changed: Vec<i64> = sqlx::query_file!(
+        "sql/endpoints/media/media_relations_state.sqlx",
+        &ids[..]
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .filter(|r| before.get(&r.id) != Some(&r.state))
+    .map(|r| r.id)
+    .collect();
+
+    if payload.dry_run {
+        tx.rollback().await?;
+    } else {
+        sqlx::query!(
+            r#"UPDATE media SET version = version + 1 WHERE id = ANY ($1::bigint[])"#,
+            &changed[..]
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        state
+            .sidecars
+            .sync(&state.storage_dir, &changed, &state.conn)
+            .await?;
+    }
+
+    Ok(Json(ApiBulkEditResult {
+        matched: before.len() as i64,
+        changed: changed.len() as i64,
+        ids: changed,
+        dry_run: payload.dry_run,
+    }))
+}
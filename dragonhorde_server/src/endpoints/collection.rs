@@ -1,14 +1,17 @@
-use crate::api_models::{ApiCollection, ApiCollectionResult};
+use crate::api_models::{ApiCollection, ApiCollectionResult, Cursor, Pagination};
 use crate::endpoints::media::Binary;
-use crate::endpoints::shared::creators_create;
+use crate::endpoints::search::{check_smart_collection, smart_collection_media};
+use crate::endpoints::shared::{collection_touch_media, creators_create, media_touch};
 use crate::error::AppError;
-use crate::error::AppError::NotFound;
+use crate::error::AppError::{BadRequest, NotFound};
 use crate::AppState;
 use axum::Json;
 use axum::extract::{Path, State};
 use axum::http::StatusCode;
 use axum::response::Redirect;
+use axum_extra::extract::Query;
 use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
 use sqlx::types::chrono::FixedOffset;
 use std::collections::{HashMap, HashSet};
 use utoipa::IntoParams;
@@ -17,24 +20,180 @@ use utoipa::IntoParams;
 #[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct CollectionResult {
     pub result: Vec<ApiCollectionResult>,
+    /// Cursor for the next page, missing on the last page or when not paging
+    pub next_cursor: Option<String>,
+    /// Number of top level collections, when asked for
+    pub total: Option<i64>,
 }
 
-#[utoipa::path(get, path = "/v1/collection", responses((status = OK, body = CollectionResult)), tags = ["collection"])]
+/// Fill in the media of a smart collection from its saved search
+async fn resolve_smart(
+    mut collection: ApiCollectionResult,
+    db: &sqlx::PgPool,
+) -> Result<ApiCollectionResult, AppError> {
+    if let Some(saved_search) = collection.saved_search {
+        collection.media = Some(smart_collection_media(saved_search, db).await?);
+    }
+    Ok(collection)
+}
+
+#[derive(utoipa::ToSchema, IntoParams, Debug, Deserialize)]
+pub struct CollectionQuery {
+    /// List the media of every sub-collection as well, by path and then position
+    #[serde(default)]
+    recursive: bool,
+}
+
+/// The media of a collection and every collection below it, by path and then position, each item
+/// listed once
+async fn tree_media(id: i64, db: &sqlx::PgPool) -> Result<Vec<i64>, AppError> {
+    let mut seen: HashSet<i64> = HashSet::new();
+    let mut media: Vec<i64> = Vec::new();
+    for node in sqlx::query_file!("sql/endpoints/collections/get_collection_tree.sqlx", id)
+        .fetch_all(db)
+        .await?
+    {
+        let node_media = match node.saved_search {
+            Some(saved_search) => smart_collection_media(saved_search, db).await?,
+            None => node.media,
+        };
+        media.extend(node_media.into_iter().filter(|m| seen.insert(*m)));
+    }
+    Ok(media)
+}
+
+/// Fill in the media of a collection that come from elsewhere, from its saved search if it's
+/// smart and from its sub-collections when asked for
+async fn resolve_media(
+    mut collection: ApiCollectionResult,
+    query: &CollectionQuery,
+    db: &sqlx::PgPool,
+) -> Result<ApiCollectionResult, AppError> {
+    if let (true, Some(id)) = (query.recursive, collection.id) {
+        collection.media = Some(tree_media(id, db).await?);
+        return Ok(collection);
+    }
+    resolve_smart(collection, db).await
+}
+
+/// Make a collection smart, as long as it has no media of its own and the search doesn't lead back
+/// to it
+async fn set_saved_search(
+    id: i64,
+    saved_search: i64,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<(), AppError> {
+    if sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM media_collection WHERE collection_id = $1) AS "exists!""#,
+        id
+    )
+    .fetch_one(&mut **tx)
+    .await?
+    {
+        return Err(BadRequest(format!(
+            "collection {} already has media of its own",
+            id
+        )));
+    }
+    sqlx::query!(
+        "UPDATE collections SET saved_search = $2 WHERE id = $1",
+        id,
+        saved_search
+    )
+    .execute(&mut **tx)
+    .await?;
+    // Checked once set, so a search naming this collection finds it smart
+    check_smart_collection(id, saved_search, &mut **tx).await?;
+    Ok(())
+}
+
+/// Order of collection listings, for their cursors
+const COLLECTION_ORDER: &str = "collection name";
+
+/// Every top level collection by name, or a page of them when per_page, last or cursor is given
+#[utoipa::path(get, path = "/v1/collection", params(Pagination), responses((status = OK, body = CollectionResult)), tags = ["collection"])]
 pub async fn get_collections(
     state: State<AppState>,
+    pagination: Query<Pagination>,
 ) -> Result<(StatusCode, Json<CollectionResult>), AppError> {
-    let found_collections = sqlx::query_file_as!(
+    let total = match pagination.total {
+        Some(_) => Some(
+            sqlx::query_scalar!(
+                r#"SELECT COUNT(*) AS "count!" FROM collections WHERE parent IS NULL"#
+            )
+            .fetch_one(&state.conn)
+            .await?,
+        ),
+        None => None,
+    };
+    if !pagination.is_paged() {
+        let found_collections = sqlx::query_file_as!(
+            ApiCollectionResult,
+            "sql/endpoints/collections/get_collections.sqlx",
+            false,
+            &vec![][..]
+        )
+        .fetch_all(&state.conn)
+        .await?;
+        return Ok((
+            StatusCode::OK,
+            Json(CollectionResult {
+                result: found_collections,
+                next_cursor: None,
+                total,
+            }),
+        ));
+    }
+
+    let cursor = pagination.cursor(COLLECTION_ORDER)?;
+    let (ids, next_cursor) = Cursor::page(
+        sqlx::query!(
+            r#"SELECT id, name
+               FROM collections
+               WHERE parent IS NULL
+                 AND ($1::text IS NULL OR (name, id) > ($1::text, $2::bigint))
+               ORDER BY name, id
+               LIMIT $3 OFFSET $4"#,
+            cursor.as_ref().and_then(|c| c.key.clone()),
+            cursor.as_ref().map(|c| c.id),
+            pagination.limit().saturating_add(1),
+            pagination.offset()
+        )
+        .fetch_all(&state.conn)
+        .await?
+        .into_iter()
+        .map(|r| (r.id, Some(r.name)))
+        .collect(),
+        pagination.limit(),
+        COLLECTION_ORDER,
+    );
+    // No ids would read as every collection
+    if ids.is_empty() {
+        return Ok((
+            StatusCode::OK,
+            Json(CollectionResult {
+                result: Vec::new(),
+                next_cursor,
+                total,
+            }),
+        ));
+    }
+
+    let mut found_collections = sqlx::query_file_as!(
         ApiCollectionResult,
         "sql/endpoints/collections/get_collections.sqlx",
         false,
-        &vec![][..]
+        &ids[..]
     )
     .fetch_all(&state.conn)
     .await?;
+    found_collections.sort_by_key(|c| ids.iter().position(|id| Some(*id) == c.id));
     Ok((
         StatusCode::OK,
         Json(CollectionResult {
             result: found_collections,
+            next_cursor,
+            total,
         }),
     ))
 }
@@ -44,6 +203,11 @@ pub async fn post_collection(
     state: State<AppState>,
     Json(payload): Json<ApiCollection>,
 ) -> Result<Json<ApiCollectionResult>, AppError> {
+    if payload.saved_search.flatten().is_some() && payload.media.is_some() {
+        return Err(BadRequest(
+            "a smart collection gets its media from its saved search".to_string(),
+        ));
+    }
     if let Some(name) = payload.name {
         //Check if already exists
         if sqlx::query_file_scalar!(
@@ -135,6 +299,9 @@ pub async fn post_collection(
             }
         }
 
+        if let Some(Some(saved_search)) = payload.saved_search {
+            set_saved_search(parent.unwrap(), saved_search, &mut tx).await?;
+        }
         tx.commit().await?;
         let r = sqlx::query_file_as!(
             ApiCollectionResult,
@@ -145,16 +312,17 @@ pub async fn post_collection(
         .fetch_one(&state.conn)
         .await?;
 
-        Ok(Json(r))
+        Ok(Json(resolve_smart(r, &state.conn).await?))
     } else {
         Err(AppError::BadRequest("name required".to_string()))
     }
 }
 
-#[utoipa::path(get, path = "/v1/collection/{id}", responses((status = OK, body = ApiCollection)), tags = ["collection"])]
+#[utoipa::path(get, path = "/v1/collection/{id}", params(CollectionQuery), responses((status = OK, body = ApiCollection)), tags = ["collection"])]
 pub async fn get_collection_id(
     state: State<AppState>,
     Path(id): Path<i64>,
+    query: Query<CollectionQuery>,
 ) -> Result<Json<ApiCollectionResult>, AppError> {
     match sqlx::query_file_as!(
         ApiCollectionResult,
@@ -166,14 +334,15 @@ pub async fn get_collection_id(
     .await?
     {
         None => Err(NotFound(format!("Collection {} not found", id))),
-        Some(c) => Ok(Json(c)),
+        Some(c) => Ok(Json(resolve_media(c, &query, &state.conn).await?)),
     }
 }
 
-#[utoipa::path(get, path = "/v1/collection/by_path/{*path}", responses((status = OK, body = ApiCollection)), tags = ["collection"])]
+#[utoipa::path(get, path = "/v1/collection/by_path/{*path}", params(CollectionQuery), responses((status = OK, body = ApiCollection)), tags = ["collection"])]
 pub async fn get_collection_path(
     state: State<AppState>,
     Path(path): Path<String>,
+    query: Query<CollectionQuery>,
 ) -> Result<Json<ApiCollectionResult>, AppError> {
     match sqlx::query_file_scalar!(
         "sql/endpoints/collections/get_collection_by_path.sqlx",
@@ -187,13 +356,18 @@ pub async fn get_collection_path(
     {
         None => Err(NotFound(format!("Collection {} not found", path))),
         Some(c) => Ok(Json(
-            sqlx::query_file_as!(
-                ApiCollectionResult,
-                "sql/endpoints/collections/get_collections.sqlx",
-                true,
-                &vec![c][..]
+            resolve_media(
+                sqlx::query_file_as!(
+                    ApiCollectionResult,
+                    "sql/endpoints/collections/get_collections.sqlx",
+                    true,
+                    &vec![c][..]
+                )
+                .fetch_one(&state.conn)
+                .await?,
+                &query,
+                &state.conn,
             )
-            .fetch_one(&state.conn)
             .await?,
         )),
     }
@@ -218,9 +392,24 @@ pub async fn patch_collection_id(
         Some(c) => c,
     };
 
+    if payload.media.is_some() && payload.saved_search.unwrap_or(r.saved_search).is_some() {
+        return Err(BadRequest(
+            "a smart collection gets its media from its saved search".to_string(),
+        ));
+    }
     let mut tx = state.conn.begin().await?;
 
-    let name = payload.name.unwrap_or(r.name.unwrap()).split("/").last().unwrap().to_string();
+    let current_name = r.name.unwrap();
+    let current_name = current_name.split("/").last().unwrap().to_string();
+    let name = payload
+        .name
+        .map_or(current_name.clone(), |n| n.split("/").last().unwrap().to_string());
+    // Renaming changes the path every item in the collection, or below it, shows
+    let mut touched: Vec<i64> = if name != current_name {
+        collection_touch_media(id, &mut tx).await?
+    } else {
+        Vec::new()
+    };
 
     sqlx::query!(
         r#"UPDATE collections SET name=$2, description=$3 WHERE id=$1"#,
@@ -235,7 +424,8 @@ pub async fn patch_collection_id(
         let current_hash: HashSet<i64> = r.media.unwrap().into_iter().collect();
         let new_hash: HashSet<i64> = media.clone().0.into_iter().collect();
 
-        let to_delete = current_hash.symmetric_difference(&new_hash);
+        let members: Vec<i64> = current_hash.union(&new_hash).copied().collect();
+        touched.extend(media_touch(&members, &mut tx).await?);
         sqlx::query!("DELETE FROM media_collection WHERE collection_id=$1", id)
             .execute(&mut *tx)
             .await?;
@@ -289,7 +479,23 @@ pub async fn patch_collection_id(
         .await?;
     }
 
+    match payload.saved_search {
+        Some(Some(saved_search)) => set_saved_search(id, saved_search, &mut tx).await?,
+        Some(None) => {
+            sqlx::query!("UPDATE collections SET saved_search = NULL WHERE id = $1", id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        None => {}
+    }
+
     tx.commit().await?;
+    touched.sort();
+    touched.dedup();
+    state
+        .sidecars
+        .sync(&state.storage_dir, &touched, &state.conn)
+        .await?;
     let r = sqlx::query_file_as!(
             ApiCollectionResult,
             "sql/endpoints/collections/get_collections.sqlx",
@@ -299,7 +505,7 @@ pub async fn patch_collection_id(
         .fetch_one(&state.conn)
         .await?;
 
-    Ok(Json(r))
+    Ok(Json(resolve_smart(r, &state.conn).await?))
 }
 
 #[derive(utoipa::ToSchema, IntoParams, Debug, Deserialize, Clone)]
@@ -319,6 +525,18 @@ pub async fn collection_id_add(
     Path(id): Path<i64>,
     Json(payload): Json<AddQuery>,
 ) -> Result<StatusCode, AppError> {
+    match sqlx::query_scalar!("SELECT saved_search FROM collections WHERE id = $1", id)
+        .fetch_optional(&state.conn)
+        .await?
+    {
+        None => return Err(NotFound(format!("Collection {} not found", id))),
+        Some(Some(_)) => {
+            return Err(BadRequest(
+                "a smart collection gets its media from its saved search".to_string(),
+            ))
+        }
+        Some(None) => {}
+    }
     let (media, order): (Vec<i64>, Vec<i32>) = payload
         .media
         .clone()
@@ -326,9 +544,16 @@ pub async fn collection_id_add(
         .map(|i| (i.media_id, i.ord))
         .collect();
 
+    let mut tx = state.conn.begin().await?;
     sqlx::query!(r#"INSERT INTO media_collection(collection_id, media_id, ord) SELECT $1, * FROM unnest($2::bigint[], $3::int[])"#,
     id, &media[..], &order[..]
-    ).execute(&state.conn).await?;
+    ).execute(&mut *tx).await?;
+    let touched = media_touch(&media, &mut tx).await?;
+    tx.commit().await?;
+    state
+        .sidecars
+        .sync(&state.storage_dir, &touched, &state.conn)
+        .await?;
     Ok(StatusCode::CREATED)
 }
 
@@ -337,6 +562,16 @@ pub async fn get_collection_id_thumbnail(
     state: State<AppState>,
     Path(id): Path<i64>,
 ) -> Result<Redirect, AppError> {
+    if let Some(Some(saved_search)) =
+        sqlx::query_scalar!("SELECT saved_search FROM collections WHERE id = $1", id)
+            .fetch_optional(&state.conn)
+            .await?
+    {
+        return match smart_collection_media(saved_search, &state.conn).await?.first() {
+            None => Err(NotFound(format!("Collection {} is empty", id))),
+            Some(m) => Ok(Redirect::temporary(&format!("/v1/media/{}/thumbnail", m))),
+        };
+    }
     match sqlx::query_scalar!(
         r#"SELECT media_collection.media_id FROM media_collection WHERE media_collection.collection_id = $1 ORDER BY media_collection.ord ASC"#,
         id
@@ -1,10 +1,12 @@
 pub(crate) use crate::api_models::api_creator::{ApiCreator, CreatorsResults};
-use crate::api_models::ApiCreatorResult;
+use crate::api_models::{ApiCreatorResult, Cursor, Pagination};
+use crate::endpoints::shared::creators_touch_media;
 use crate::error::AppError;
 use crate::error::AppError::NotFound;
 use crate::AppState;
 use axum::extract::{Path, State};
 use axum::Json;
+use axum_extra::extract::Query;
 use sqlx::types::chrono::FixedOffset;
 use std::collections::HashSet;
 
@@ -22,16 +24,82 @@ async fn check_creator(id: i64, db: &sqlx::PgPool) -> Result<ApiCreatorResult, A
     }
 }
 
-#[utoipa::path(get, path = "/v1/creators", responses((status = OK, body = CreatorsResults)), tags = ["creators"])]
-pub async fn get_creators(state: State<AppState>) -> Result<Json<CreatorsResults>, AppError> {
-    let creators = sqlx::query_file_as!(
+/// Order of creator listings, for their cursors
+const CREATOR_ORDER: &str = "creator name";
+
+/// Every creator by name, or a page of them when per_page, last or cursor is given
+#[utoipa::path(get, path = "/v1/creators", params(Pagination), responses((status = OK, body = CreatorsResults)), tags = ["creators"])]
+pub async fn get_creators(
+    state: State<AppState>,
+    pagination: Query<Pagination>,
+) -> Result<Json<CreatorsResults>, AppError> {
+    let total = match pagination.total {
+        Some(_) => Some(
+            sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM creators"#)
+                .fetch_one(&state.conn)
+                .await?,
+        ),
+        None => None,
+    };
+    if !pagination.is_paged() {
+        let creators = sqlx::query_file_as!(
+            ApiCreatorResult,
+            "sql/endpoints/creators/get_creators.sqlx",
+            &vec![][..]
+        )
+        .fetch_all(&state.conn)
+        .await?;
+        return Ok(Json(CreatorsResults {
+            result: creators,
+            next_cursor: None,
+            total,
+        }));
+    }
+
+    let cursor = pagination.cursor(CREATOR_ORDER)?;
+    let (ids, next_cursor) = Cursor::page(
+        sqlx::query!(
+            r#"SELECT id, name
+               FROM creators
+               WHERE $1::text IS NULL OR (name, id) > ($1::text, $2::bigint)
+               ORDER BY name, id
+               LIMIT $3 OFFSET $4"#,
+            cursor.as_ref().and_then(|c| c.key.clone()),
+            cursor.as_ref().map(|c| c.id),
+            pagination.limit().saturating_add(1),
+            pagination.offset()
+        )
+        .fetch_all(&state.conn)
+        .await?
+        .into_iter()
+        .map(|r| (r.id, Some(r.name)))
+        .collect(),
+        pagination.limit(),
+        CREATOR_ORDER,
+    );
+    // No ids would read as every creator
+    if ids.is_empty() {
+        return Ok(Json(CreatorsResults {
+            result: Vec::new(),
+            next_cursor,
+            total,
+        }));
+    }
+
+    let mut creators = sqlx::query_file_as!(
         ApiCreatorResult,
         "sql/endpoints/creators/get_creators.sqlx",
-        &vec![][..]
+        &ids[..]
     )
     .fetch_all(&state.conn)
     .await?;
-    Ok(Json(CreatorsResults { result: creators }))
+    // Names can repeat, so put them back in the page's order
+    creators.sort_by_key(|c| ids.iter().position(|id| Some(*id) == c.id));
+    Ok(Json(CreatorsResults {
+        result: creators,
+        next_cursor,
+        total,
+    }))
 }
 
 #[utoipa::path(get, path = "/v1/creators/{id}", responses((status = OK, body = ApiCreator)), tags = ["creators"])]
@@ -72,13 +140,17 @@ pub async fn patch_creators_id(
 
     let mut tx = state.conn.begin().await?;
 
-    sqlx::query!(
-        r#"UPDATE creators SET name=$2 WHERE id = $1"#,
-        id,
-        payload.name.unwrap_or(creator.name.unwrap())
-    )
-    .execute(&mut *tx)
-    .await?;
+    let current_name = creator.name.unwrap();
+    let name = payload.name.unwrap_or(current_name.clone());
+    // The name shows on every item by the creator, its aliases don't
+    let touched = if name != current_name {
+        creators_touch_media(&[id], &mut tx).await?
+    } else {
+        Vec::new()
+    };
+    sqlx::query!(r#"UPDATE creators SET name=$2 WHERE id = $1"#, id, name)
+        .execute(&mut *tx)
+        .await?;
 
     if let Some(aliases) = payload.aliases {
         let current_aliases: HashSet<String> = HashSet::from_iter(creator.aliases.unwrap());
@@ -107,6 +179,10 @@ pub async fn patch_creators_id(
         }
     }
     tx.commit().await?;
+    state
+        .sidecars
+        .sync(&state.storage_dir, &touched, &state.conn)
+        .await?;
 
     Ok(Json(
         check_creator(id, &state.conn).await?,
@@ -1,15 +1,19 @@
 use sqlx::types::chrono::FixedOffset;
 use std::collections::{BTreeMap, HashMap};
 
-use crate::api_models::{ApiMedia, ApiMediaReturn, ImageMetadata, ImageResolution};
+use crate::api_models::{
+    ApiMedia, ApiMediaPatch, ApiMediaReturn, ApiSource, ImageMetadata, ImageResolution, ListPatch,
+    SearchResult, SimilarQuery, SourceQuery, TagGroupsPatch,
+};
 use crate::error::AppError;
-use crate::error::AppError::{BadRequest, Exists, Internal, NotFound};
+use crate::error::AppError::{BadRequest, Exists, Internal, NotFound, PreconditionFailed};
 use crate::AppState;
 use axum::body::{Body, Bytes};
 use axum::extract::{Path, State};
 use axum::http::{header, HeaderMap};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
+use axum_extra::extract::Query;
 use dragonhorde_common::hash::{perceptual, sha256};
 
 use axum_typed_multipart::{FieldData, TryFromMultipart, TypedMultipart};
@@ -21,7 +25,8 @@ use std::fs::File;
 use std::io::{Cursor, Write};
 use tokio::io::AsyncReadExt;
 use utoipa::ToSchema;
-use crate::endpoints::shared::creators_create;
+use crate::endpoints::shared::{creators_create, tags_implied, tags_resolve_aliases};
+use crate::canonical::{CanonicalSource, Canonicaliser};
 
 /// Check if the media item exists and return it as ApiMedia, raise a AppError:NotFound
 async fn load_media_item(id: i64, db: &PgPool) -> Result<ApiMediaReturn, AppError> {
@@ -74,6 +79,7 @@ pub async fn post_media(
         },
         bits_per_pixel: im.color().bits_per_pixel(),
         transparent: im.color().has_alpha(),
+        file_size: Some(file.contents.len() as u64),
     };
 
     let hash = sha256(&file.contents);
@@ -119,7 +125,7 @@ pub async fn post_media(
     }
 
     if let Some(sources) = payload.sources {
-        sources_insert(&sources.0, id, &mut tx).await?;
+        sources_insert(&sources, id, &state.canonicaliser, &mut tx).await?;
     }
 
     if let Some(collections) = payload.collections {
@@ -138,7 +144,13 @@ pub async fn post_media(
 
     //End of Transaction
     match tx.commit().await {
-        Ok(_) => Ok(Json(Some(load_media_item(id, &state.conn).await?))),
+        Ok(_) => {
+            let item = load_media_item(id, &state.conn).await?;
+            if let Err(e) = state.sidecars.write(&state.storage_dir, &item) {
+                tracing::error!("failed to write sidecar for media {}: {e}", id);
+            }
+            Ok(Json(Some(item)))
+        }
         Err(e) => {
             //If the transaction fails, remove the files
             std::fs::remove_file(media_path).ok();
@@ -148,48 +160,135 @@ pub async fn post_media(
     }
 }
 
-#[utoipa::path(patch, path = "/v1/media/{id}", request_body = ApiMedia , responses((status = OK, body = ApiMedia)), tags = ["media"])]
+/// Version the client based its change on, from an If-Match header or the payload
+fn expected_version(headers: &HeaderMap, version: Option<i64>) -> Result<Option<i64>, AppError> {
+    match headers.get(header::IF_MATCH) {
+        None => Ok(version),
+        Some(value) => {
+            let value = value
+                .to_str()
+                .map_err(|_| BadRequest("invalid If-Match header".to_string()))?
+                .trim();
+            if value == "*" {
+                return Ok(None);
+            }
+            value
+                .trim_start_matches("W/")
+                .trim_matches('"')
+                .parse::<i64>()
+                .map(Some)
+                .map_err(|_| BadRequest(format!("invalid If-Match header {}", value)))
+        }
+    }
+}
+
+fn etag_headers(version: i64) -> Result<HeaderMap, AppError> {
+    let mut headers: HeaderMap = HeaderMap::new();
+    headers.insert(header::ETAG, format!("\"{}\"", version).parse()?);
+    Ok(headers)
+}
+
+/// Apply the creator, source, tag and collection parts of a patch to a media item
+pub(crate) async fn media_patch_relations(
+    payload: &ApiMediaPatch,
+    id: i64,
+    canonicaliser: &Canonicaliser,
+    db: &mut Transaction<'_, Postgres>,
+) -> Result<(), AppError> {
+    match &payload.sources {
+        None => {}
+        Some(ListPatch::Replace(sources)) => {
+            sources_insert(sources, id, canonicaliser, db).await?;
+            sources_delete(sources, id, canonicaliser, db).await?;
+        }
+        Some(ListPatch::Operations(ops)) => {
+            sources_insert(&ops.add, id, canonicaliser, db).await?;
+            sources_remove(&ops.remove, id, canonicaliser, db).await?;
+        }
+    }
+
+    match &payload.creators {
+        None => {}
+        Some(ListPatch::Replace(creators)) => {
+            creators_media_create(creators.clone(), id, db).await?;
+            creators_delete(creators.clone(), id, db).await?;
+        }
+        Some(ListPatch::Operations(ops)) => {
+            creators_media_create(ops.add.clone(), id, db).await?;
+            creators_remove(&ops.remove, id, db).await?;
+        }
+    }
+
+    match &payload.tag_groups {
+        None => {}
+        Some(TagGroupsPatch::Replace(tags)) => {
+            tags_insert(tags, id, db).await?;
+            tags_delete(tags, id, db).await?;
+        }
+        Some(TagGroupsPatch::Operations(ops)) => {
+            tags_insert(&ops.add, id, db).await?;
+            tags_remove(&ops.remove, id, db).await?;
+        }
+    }
+
+    match &payload.collections {
+        None => {}
+        Some(ListPatch::Replace(collections)) => {
+            collections_insert(collections, id, db).await?;
+            collections_delete(collections, id, db).await?;
+        }
+        Some(ListPatch::Operations(ops)) => {
+            collections_insert(&ops.add, id, db).await?;
+            collections_remove(&ops.remove, id, db).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Update a media item. Creators, sources, tag groups and collections may either be sent as the
+/// complete new set, or as `{"add": ..., "remove": ...}` operations on the current one.
+#[utoipa::path(patch, path = "/v1/media/{id}", request_body = ApiMediaPatch, responses((status = OK, body = ApiMedia), (status = PRECONDITION_FAILED, description = "The item has changed since the given version")), tags = ["media"])]
 pub async fn media_item_patch(
     state: State<AppState>,
     Path(id): Path<i64>,
-    Json(payload): Json<ApiMedia>,
-) -> Result<(), AppError> {
+    headers: HeaderMap,
+    Json(payload): Json<ApiMediaPatch>,
+) -> Result<(HeaderMap, Json<ApiMediaReturn>), AppError> {
     let item = load_media_item(id, &state.conn).await?;
+    let expected = expected_version(&headers, payload.version)?;
     let mut tx = state.conn.begin().await?;
 
-    sqlx::query!(
-        r#"UPDATE media SET created = $2, title = $3, description = $4 WHERE id = $1"#,
+    if sqlx::query_scalar!(
+        r#"UPDATE media SET created = $2, title = $3, description = $4, version = version + 1
+           WHERE id = $1 AND ($5::bigint IS NULL OR version = $5)
+           RETURNING version"#,
         id,
         payload.created.or(item.created) as Option<chrono::DateTime<FixedOffset>>,
-        payload.title.or(item.title),
-        payload.description.or(item.description.clone())
+        payload.title.clone().or(item.title),
+        payload.description.clone().or(item.description.clone()),
+        expected
     )
-    .execute(&mut *tx)
-    .await?;
-
-    if let Some(sources) = payload.sources {
-        sources_insert(&sources.0, id, &mut tx).await?;
-        sources_delete(&sources.0, id, &mut tx).await?;
-    }
-
-    if let Some(creators) = payload.creators {
-        creators_media_create(creators.0.clone(), id, &mut tx).await?;
-        creators_delete(creators.0, id, &mut tx).await?
+    .fetch_optional(&mut *tx)
+    .await?
+    .is_none()
+    {
+        return Err(PreconditionFailed(format!(
+            "media {} has changed since version {}",
+            id,
+            expected.unwrap_or_default()
+        )));
     }
 
-    if let Some(tags) = payload.tag_groups {
-        tags_insert(&tags.0, id, &mut tx).await?;
-        tags_delete(&tags.0, id, &mut tx).await?;
-    }
-    if let Some(collections) = payload.collections {
-        collections_insert(&collections.0, id, &mut tx).await?;
-        collections_delete(&collections.0, id, &mut tx).await?;
-    }
+    media_patch_relations(&payload, id, &state.canonicaliser, &mut tx).await?;
 
     tx.commit().await?;
     //End of Transaction
 
-    Ok(())
+    let item = load_media_item(id, &state.conn).await?;
+    if let Err(e) = state.sidecars.write(&state.storage_dir, &item) {
+        tracing::error!("failed to write sidecar for media {}: {e}", id);
+    }
+    Ok((etag_headers(item.version)?, Json(item)))
 }
 
 #[utoipa::path(get, path = "/v1/media/{id}", responses((status = OK, body = ApiMedia)), tags = ["media"]
@@ -197,8 +296,9 @@ pub async fn media_item_patch(
 pub async fn get_media_item(
     state: State<AppState>,
     Path(id): Path<i64>,
-) -> Result<Json<ApiMediaReturn>, AppError> {
-    Ok(Json(load_media_item(id, &state.conn).await?))
+) -> Result<(HeaderMap, Json<ApiMediaReturn>), AppError> {
+    let item = load_media_item(id, &state.conn).await?;
+    Ok((etag_headers(item.version)?, Json(item)))
 }
 
 #[utoipa::path(delete, path = "/v1/media/{id}", responses((status = OK)), tags = ["media"]
@@ -211,7 +311,9 @@ pub async fn delete_media_item(
     sqlx::query!(r#"DELETE FROM media WHERE id = $1"#, item.id)
         .execute(&state.conn)
         .await?;
-    let path = &state.storage_dir.join(item.storage_uri.unwrap().clone());
+    let storage_uri = item.storage_uri.unwrap();
+    state.sidecars.remove(&state.storage_dir, &storage_uri);
+    let path = &state.storage_dir.join(storage_uri);
     if path.exists() {
         std::fs::remove_file(path).ok();
     }
@@ -237,6 +339,60 @@ pub async fn get_media_item_by_hash(
     }
 }
 
+#[utoipa::path(get, path = "/v1/media/by_source", params(SourceQuery), responses((status = OK, body = SearchResult)), tags = ["media"])]
+pub async fn get_media_item_by_source(
+    state: State<AppState>,
+    query: Query<SourceQuery>,
+) -> Result<Json<SearchResult>, AppError> {
+    let (url, site, post_id) = match (&query.url, &query.site, &query.post_id) {
+        (Some(url), _, _) => {
+            let canonical = state.canonicaliser.canonicalise(url);
+            (Some(canonical.url), canonical.site, canonical.post_id)
+        }
+        (None, Some(site), Some(post_id)) => (None, Some(site.to_lowercase()), Some(post_id.clone())),
+        _ => return Err(BadRequest("url, or site and post_id, required".to_string())),
+    };
+
+    let r = sqlx::query_scalar!(
+        r#"
+        SELECT media_id
+        FROM sources
+        WHERE source = $1
+           OR ($3::text IS NOT NULL AND site = $2 AND post_id = $3)
+        GROUP BY media_id
+        ORDER BY media_id"#,
+        url,
+        site,
+        post_id
+    )
+    .fetch_all(&state.conn)
+    .await?;
+
+    if r.is_empty() {
+        return Err(NotFound(format!(
+            "media with source {} not found",
+            query.url.clone().unwrap_or_else(|| format!(
+                "{}/{}",
+                site.unwrap_or_default(),
+                post_id.unwrap_or_default()
+            ))
+        )));
+    }
+
+    let perceptual_hash: Option<BitVec> = None;
+    Ok(Json(SearchResult {
+        result: sqlx::query_file_as!(
+            ApiMediaReturn,
+            "sql/media_item_get.sqlx",
+            &r[..],
+            perceptual_hash
+        )
+        .fetch_all(&state.conn)
+        .await?,
+        ..Default::default()
+    }))
+}
+
 fn extension_to_mime(ext: &str) -> &'static str {
     match ext {
         "apng" => "image/apng",
@@ -371,6 +527,48 @@ pub async fn get_media_item_tags(
     Ok(Json(r))
 }
 
+/// Items related to this one, by the tags and creators they share. Rare tags and creators count
+/// for more than common ones, and a close perceptual hash can count too when `hash_weight` is given
+#[utoipa::path(get, path = "/v1/media/{id}/similar", params(SimilarQuery), responses((status = OK, body = SearchResult)), tags = ["media"]
+)]
+pub async fn get_media_item_similar(
+    state: State<AppState>,
+    Path(id): Path<i64>,
+    query: Query<SimilarQuery>,
+) -> Result<Json<SearchResult>, AppError> {
+    let item = load_media_item(id, &state.conn).await?;
+    let hash_weight = query.hash_weight.unwrap_or_default();
+    let r: Vec<i64> = sqlx::query_file!(
+        "sql/endpoints/media/media_similar.sqlx",
+        id,
+        hash_weight,
+        query.limit.unwrap_or(20),
+        query.offset
+    )
+    .fetch_all(&state.conn)
+    .await?
+    .into_iter()
+    .map(|i| i.id)
+    .collect();
+
+    // Report how far each item's image is from this one when that counted
+    let perceptual_hash: Option<BitVec> = match (hash_weight != 0.0, item.perceptual_hash) {
+        (true, Some(hash)) => Some(BitVec::from_bytes(&hash.to_be_bytes())),
+        _ => None,
+    };
+    Ok(Json(SearchResult {
+        result: sqlx::query_file_as!(
+            ApiMediaReturn,
+            "sql/media_item_get.sqlx",
+            &r[..],
+            perceptual_hash
+        )
+        .fetch_all(&state.conn)
+        .await?,
+        ..Default::default()
+    }))
+}
+
 pub async fn creators_media_create(
     creators_in: Vec<String>,
     id: i64,
@@ -410,6 +608,30 @@ async fn creators_delete(
     Ok(())
 }
 
+async fn creators_remove(
+    creators_in: &Vec<String>,
+    id: i64,
+    db: &mut Transaction<'_, Postgres>,
+) -> Result<(), AppError> {
+    if !creators_in.is_empty() {
+        sqlx::query!(
+            r#"
+            DELETE
+            FROM media_creators
+            WHERE media_id = $1
+              AND creator_id IN (SELECT creator FROM creator_alias WHERE alias = ANY ($2::varchar[]))"#,
+            id,
+            &creators_in
+                .iter()
+                .map(|s| s.to_lowercase())
+                .collect::<Vec<String>>()
+        )
+        .execute(&mut **db)
+        .await?;
+    }
+    Ok(())
+}
+
 pub async fn collections_insert(
     collections_in: &Vec<String>,
     id: i64,
@@ -490,7 +712,7 @@ pub async fn collections_delete(
                 collection_ids.push(result);
             }
         }
-        sqlx::query!(r#"DELETE FROM media_collection WHERE media_id = $1 AND collection_id != any($2::bigint[]) "#, id, &collection_ids[..])
+        sqlx::query!(r#"DELETE FROM media_collection WHERE media_id = $1 AND NOT collection_id = any($2::bigint[]) "#, id, &collection_ids[..])
             .execute(&mut **db)
             .await?;
     } else {
@@ -502,18 +724,74 @@ pub async fn collections_delete(
     Ok(())
 }
 
+pub async fn collections_remove(
+    collections_in: &Vec<String>,
+    id: i64,
+    db: &mut Transaction<'_, Postgres>,
+) -> Result<(), AppError> {
+    let mut collection_ids: Vec<i64> = Vec::new();
+    for collection in collections_in {
+        if let Some(r) = sqlx::query_file_scalar!(
+            "sql/endpoints/collections/get_collection_by_path.sqlx",
+            &collection
+                .split("/")
+                .map(|i| i.to_string())
+                .collect::<Vec<String>>()
+        )
+        .fetch_optional(&mut **db)
+        .await?
+        {
+            collection_ids.push(r);
+        }
+    }
+    if !collection_ids.is_empty() {
+        sqlx::query!(
+            r#"DELETE FROM media_collection WHERE media_id = $1 AND collection_id = any($2::bigint[])"#,
+            id,
+            &collection_ids[..]
+        )
+        .execute(&mut **db)
+        .await?;
+    }
+    Ok(())
+}
+
 async fn sources_insert(
-    sources: &Vec<String>,
+    sources: &Vec<ApiSource>,
     id: i64,
+    canonicaliser: &Canonicaliser,
     db: &mut Transaction<'_, Postgres>,
 ) -> Result<(), AppError> {
+    let canonical: Vec<CanonicalSource> = sources
+        .iter()
+        .map(|s| canonicaliser.canonicalise(&s.url))
+        .collect();
+    let urls: Vec<String> = canonical.iter().map(|c| c.url.clone()).collect();
+    let sites: Vec<Option<String>> = canonical.iter().map(|c| c.site.clone()).collect();
+    let post_ids: Vec<Option<String>> = canonical.iter().map(|c| c.post_id.clone()).collect();
+    let types: Vec<Option<String>> = sources
+        .iter()
+        .map(|s| s.source_type.as_ref().map(|t| t.as_str().to_string()))
+        .collect();
+    let titles: Vec<Option<String>> = sources.iter().map(|s| s.title.clone()).collect();
+    // Re-sending a known source only fills in details, it never clears them
     sqlx::query!(
         r#"
-                    INSERT INTO sources (media_id, source)
-                    VALUES ($1, UNNEST($2::text[]))
-                    ON CONFLICT DO NOTHING"#,
+                    INSERT INTO sources (media_id, source, type, source_title, site, post_id)
+                    SELECT DISTINCT ON (u.source) $1, u.source, u.type::source_type, u.title, u.site, u.post_id
+                    FROM UNNEST($2::text[], $3::text[], $4::text[], $5::text[], $6::text[])
+                        AS u(source, type, title, site, post_id)
+                    ON CONFLICT (media_id, source) DO UPDATE
+                        SET type         = COALESCE(EXCLUDED.type, sources.type),
+                            source_title = COALESCE(EXCLUDED.source_title, sources.source_title),
+                            site         = EXCLUDED.site,
+                            post_id      = EXCLUDED.post_id"#,
         id,
-        &sources[..]
+        &urls[..],
+        &types[..] as &[Option<String>],
+        &titles[..] as &[Option<String>],
+        &sites[..] as &[Option<String>],
+        &post_ids[..] as &[Option<String>]
     )
     .execute(&mut **db)
     .await?;
@@ -521,18 +799,23 @@ async fn sources_insert(
 }
 
 async fn sources_delete(
-    sources: &Vec<String>,
+    sources: &Vec<ApiSource>,
     id: i64,
+    canonicaliser: &Canonicaliser,
     db: &mut Transaction<'_, Postgres>,
 ) -> Result<(), AppError> {
+    let urls: Vec<String> = sources
+        .iter()
+        .map(|s| canonicaliser.canonicalise(&s.url).url)
+        .collect();
     sqlx::query!(
         r#"
                    DELETE
                    FROM sources
                    WHERE media_id = $1
-                     AND source != ANY ($2::varchar[])"#,
+                     AND NOT source = ANY ($2::varchar[])"#,
         id,
-        &sources[..]
+        &urls[..]
     )
     .execute(&mut **db)
     .await?;
@@ -540,6 +823,28 @@ async fn sources_delete(
     Ok(())
 }
 
+async fn sources_remove(
+    sources: &Vec<ApiSource>,
+    id: i64,
+    canonicaliser: &Canonicaliser,
+    db: &mut Transaction<'_, Postgres>,
+) -> Result<(), AppError> {
+    if !sources.is_empty() {
+        let urls: Vec<String> = sources
+            .iter()
+            .map(|s| canonicaliser.canonicalise(&s.url).url)
+            .collect();
+        sqlx::query!(
+            r#"DELETE FROM sources WHERE media_id = $1 AND source = ANY ($2::varchar[])"#,
+            id,
+            &urls[..]
+        )
+        .execute(&mut **db)
+        .await?;
+    }
+    Ok(())
+}
+
 async fn tags_insert(
     tags: &BTreeMap<String, Vec<String>>,
     id: i64,
@@ -554,6 +859,11 @@ async fn tags_insert(
         })
         .flatten()
         .collect();
+    // Aliases are added as the tag they stand for
+    let resolved = tags_resolve_aliases(tag_tuple.iter().map(|t| t.1.clone()).collect(), db).await?;
+    for (tg, tag) in tag_tuple.iter_mut().zip(resolved) {
+        tg.1 = tag;
+    }
     tag_tuple.sort_unstable_by_key(|tg| tg.1.clone());
     tag_tuple.dedup_by_key(|tg| tg.1.clone());
 
@@ -632,6 +942,8 @@ async fn tags_insert(
             .await?,
         );
     }
+    // Along with everything they imply
+    let existing_ids = tags_implied(existing_ids, db).await?;
     sqlx::query!(
         "INSERT INTO media_tags(media_id, tag_id) SELECT $1, * FROM unnest($2::bigint[]) ON CONFLICT DO NOTHING ",
         id,
@@ -647,11 +959,29 @@ async fn tags_delete(
     id: i64,
     db: &mut Transaction<'_, Postgres>,
 ) -> Result<(), AppError> {
-    let mut new_tags = tags
-        .values()
-        .flatten()
-        .map(|i| i.to_string())
-        .collect::<Vec<String>>();
+    let mut new_tags = tags_resolve_aliases(
+        tags.values()
+            .flatten()
+            .map(|i| i.to_lowercase())
+            .collect::<Vec<String>>(),
+        db,
+    )
+    .await?;
+    // The tags implied by those kept were just added, so they stay too
+    let kept = sqlx::query_scalar!(
+        "SELECT id FROM tags WHERE tag = ANY ($1::varchar[])",
+        &new_tags[..]
+    )
+    .fetch_all(&mut **db)
+    .await?;
+    new_tags.extend(
+        sqlx::query_scalar!(
+            "SELECT tag FROM tags WHERE id = ANY ($1::bigint[])",
+            &tags_implied(kept, db).await?[..]
+        )
+        .fetch_all(&mut **db)
+        .await?,
+    );
     new_tags.sort();
     new_tags.dedup();
     sqlx::query_file!("sql/endpoints/media/tags_delete.sqlx", id, &new_tags[..])
@@ -659,3 +989,24 @@ async fn tags_delete(
         .await?;
     Ok(())
 }
+
+async fn tags_remove(
+    tags: &Vec<String>,
+    id: i64,
+    db: &mut Transaction<'_, Postgres>,
+) -> Result<(), AppError> {
+    if !tags.is_empty() {
+        sqlx::query!(
+            r#"
+            DELETE
+            FROM media_tags
+            WHERE media_id = $1
+              AND tag_id IN (SELECT id FROM tags WHERE tag = ANY ($2::varchar[]))"#,
+            id,
+            &tags_resolve_aliases(tags.iter().map(|t| t.to_lowercase()).collect(), db).await?[..]
+        )
+        .execute(&mut **db)
+        .await?;
+    }
+    Ok(())
+}
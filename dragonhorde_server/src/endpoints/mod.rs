@@ -7,4 +7,6 @@ pub(crate) mod creators;
 
 mod relations;
 pub(crate) mod duplicates;
+pub(crate) mod bulk;
+pub(crate) mod saved_searches;
 mod shared;
@@ -0,0 +1,192 @@
+use crate::api_models::{
+    ApiSavedSearch, ApiSavedSearchResult, Pagination, SavedSearchesResults, SearchQueryJson,
+    SearchResult, Sort,
+};
+use crate::endpoints::search::{check_smart_collection, json_query_expr, run_search_json};
+use crate::error::AppError;
+use crate::error::AppError::{BadRequest, Exists, NotFound};
+use crate::query::QueryError;
+use crate::AppState;
+use axum::extract::{Path, State};
+use axum::Json;
+use axum_extra::extract::Query;
+use sqlx::types::chrono::FixedOffset;
+
+pub(crate) async fn load_saved_search(
+    id: i64,
+    db: impl sqlx::PgExecutor<'_>,
+) -> Result<ApiSavedSearchResult, AppError> {
+    let r = sqlx::query!(
+        r#"SELECT id,
+                  name,
+                  query AS "query: sqlx::types::Json<SearchQueryJson>",
+                  sort AS "sort: sqlx::types::Json<Sort>",
+                  created AS "created: chrono::DateTime<FixedOffset>",
+                  updated AS "updated: chrono::DateTime<FixedOffset>"
+           FROM saved_searches
+           WHERE id = $1"#,
+        id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(NotFound(format!("saved search {} not found", id)))?;
+    Ok(ApiSavedSearchResult {
+        id: r.id,
+        name: r.name,
+        query: r.query.0,
+        sort: r.sort.0,
+        created: r.created,
+        updated: r.updated,
+    })
+}
+
+async fn check_name_free(name: &str, id: Option<i64>, db: &sqlx::PgPool) -> Result<(), AppError> {
+    let existing = sqlx::query_scalar!("SELECT id FROM saved_searches WHERE name = $1", name)
+        .fetch_optional(db)
+        .await?;
+    if existing.is_some_and(|existing| Some(existing) != id) {
+        return Err(Exists(format!("saved search {} already exists", name)));
+    }
+    Ok(())
+}
+
+#[utoipa::path(get, path = "/v1/saved_searches", responses((status = OK, body = SavedSearchesResults)), tags = ["search"])]
+pub async fn get_saved_searches(
+    state: State<AppState>,
+) -> Result<Json<SavedSearchesResults>, AppError> {
+    let result = sqlx::query!(
+        r#"SELECT id,
+                  name,
+                  query AS "query: sqlx::types::Json<SearchQueryJson>",
+                  sort AS "sort: sqlx::types::Json<Sort>",
+                  created AS "created: chrono::DateTime<FixedOffset>",
+                  updated AS "updated: chrono::DateTime<FixedOffset>"
+           FROM saved_searches
+           ORDER BY name"#
+    )
+    .fetch_all(&state.conn)
+    .await?
+    .into_iter()
+    .map(|r| ApiSavedSearchResult {
+        id: r.id,
+        name: r.name,
+        query: r.query.0,
+        sort: r.sort.0,
+        created: r.created,
+        updated: r.updated,
+    })
+    .collect();
+    Ok(Json(SavedSearchesResults { result }))
+}
+
+#[utoipa::path(post, path = "/v1/saved_searches", request_body = ApiSavedSearch, responses((status = OK, body = ApiSavedSearchResult), (status = BAD_REQUEST, body = QueryError)), tags = ["search"])]
+pub async fn post_saved_search(
+    state: State<AppState>,
+    Json(payload): Json<ApiSavedSearch>,
+) -> Result<Json<ApiSavedSearchResult>, AppError> {
+    let (Some(name), Some(query)) = (payload.name, payload.query) else {
+        return Err(BadRequest(
+            "a saved search needs a name and a query".to_string(),
+        ));
+    };
+    // Refuse searches that could never run
+    json_query_expr(&query)?;
+    check_name_free(&name, None, &state.conn).await?;
+
+    let id = sqlx::query_scalar!(
+        r#"INSERT INTO saved_searches (name, query, sort) VALUES ($1, $2, $3) RETURNING id"#,
+        name,
+        serde_json::to_value(&query)?,
+        serde_json::to_value(payload.sort.unwrap_or_default())?
+    )
+    .fetch_one(&state.conn)
+    .await?;
+    Ok(Json(load_saved_search(id, &state.conn).await?))
+}
+
+#[utoipa::path(get, path = "/v1/saved_searches/{id}", responses((status = OK, body = ApiSavedSearchResult)), tags = ["search"])]
+pub async fn get_saved_search(
+    state: State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<ApiSavedSearchResult>, AppError> {
+    Ok(Json(load_saved_search(id, &state.conn).await?))
+}
+
+/// Change the name, query or sort of a saved search. Anything left out is kept
+#[utoipa::path(patch, path = "/v1/saved_searches/{id}", request_body = ApiSavedSearch, responses((status = OK, body = ApiSavedSearchResult), (status = BAD_REQUEST, body = QueryError)), tags = ["search"])]
+pub async fn patch_saved_search(
+    state: State<AppState>,
+    Path(id): Path<i64>,
+    Json(payload): Json<ApiSavedSearch>,
+) -> Result<Json<ApiSavedSearchResult>, AppError> {
+    let current = load_saved_search(id, &state.conn).await?;
+    if let Some(query) = &payload.query {
+        json_query_expr(query)?;
+    }
+    if let Some(name) = &payload.name {
+        check_name_free(name, Some(id), &state.conn).await?;
+    }
+
+    let mut tx = state.conn.begin().await?;
+    let query_changed = payload.query.is_some();
+    sqlx::query!(
+        r#"UPDATE saved_searches SET name = $2, query = $3, sort = $4, updated = now() WHERE id = $1"#,
+        id,
+        payload.name.unwrap_or(current.name),
+        serde_json::to_value(payload.query.unwrap_or(current.query))?,
+        serde_json::to_value(payload.sort.unwrap_or(current.sort))?
+    )
+    .execute(&mut *tx)
+    .await?;
+    // The new query mustn't lead a smart collection it backs back to itself
+    if query_changed {
+        for collection in
+            sqlx::query_scalar!("SELECT id FROM collections WHERE saved_search = $1", id)
+                .fetch_all(&mut *tx)
+                .await?
+        {
+            check_smart_collection(collection, id, &mut *tx).await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(Json(load_saved_search(id, &state.conn).await?))
+}
+
+#[utoipa::path(delete, path = "/v1/saved_searches/{id}", responses((status = OK)), tags = ["search"])]
+pub async fn delete_saved_search(
+    state: State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<(), AppError> {
+    if let Some(collection) = sqlx::query_scalar!(
+        "SELECT name FROM collections WHERE saved_search = $1 LIMIT 1",
+        id
+    )
+    .fetch_optional(&state.conn)
+    .await?
+    {
+        return Err(BadRequest(format!(
+            "saved search {} backs the smart collection {}",
+            id, collection
+        )));
+    }
+    let result = sqlx::query!("DELETE FROM saved_searches WHERE id = $1", id)
+        .execute(&state.conn)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(NotFound(format!("saved search {} not found", id)));
+    }
+    Ok(())
+}
+
+/// Run a saved search in its saved order
+#[utoipa::path(get, path = "/v1/saved_searches/{id}/search", params(Pagination), responses((status = OK, body = SearchResult), (status = BAD_REQUEST, body = QueryError)), tags = ["search"])]
+pub async fn run_saved_search(
+    state: State<AppState>,
+    Path(id): Path<i64>,
+    pagination: Query<Pagination>,
+) -> Result<Json<SearchResult>, AppError> {
+    let saved = load_saved_search(id, &state.conn).await?;
+    Ok(Json(
+        run_search_json(&saved.query, &saved.sort, pagination.0, &state.conn).await?,
+    ))
+}
@@ -1,8 +1,11 @@
 use crate::api_models::{
-    ApiCollectionResult, ApiMediaReturn, HashQuery, Pagination, QueryType, SearchQuery,
-    SearchQueryJson, SearchResult,
+    ApiCollectionResult, ApiMediaReturn, ApiSource, Cursor, HashQuery, Pagination, QueryType,
+    RandomQuery, SearchQuery, SearchQueryJson, SearchResult, Sort, SortBy,
 };
+use crate::endpoints::saved_searches::load_saved_search;
 use crate::error::AppError;
+use crate::error::AppError::{BadRequest, NotFound};
+use crate::query::{self, DateRange, Expr, QueryError, TagMatch, Term};
 use crate::AppState;
 use axum::extract::State;
 use axum::http::StatusCode;
@@ -10,176 +13,240 @@ use axum::Json;
 use axum_extra::extract::Query;
 use chrono::FixedOffset;
 use sqlx::types::BitVec;
+use sqlx::PgConnection;
 use std::collections::HashMap;
+use std::pin::Pin;
 
-#[utoipa::path(get, path = "/v1/search", params(SearchQuery, Pagination), responses((status = OK, body = SearchResult)), tags = ["search"]
+#[utoipa::path(get, path = "/v1/search", params(SearchQuery, Pagination, Sort), responses((status = OK, body = SearchResult), (status = BAD_REQUEST, body = QueryError)), tags = ["search"]
 )]
 pub async fn search_query(
     state: State<AppState>,
     query: Query<SearchQuery>,
     pagination: Query<Pagination>,
+    sort: Query<Sort>,
 ) -> Result<(StatusCode, Json<SearchResult>), AppError> {
-    let collections_include: Vec<String> = Vec::new();
-    let collections_exclude: Vec<String> = Vec::new();
-    let mut creators_include: Vec<String> = Vec::new();
-    let creators_exclude: Vec<String> = Vec::new();
-    let mut tags_include: Vec<String> = Vec::new();
-    let mut tags_exclude: Vec<String> = Vec::new();
-    dbg!(&query);
-    if !query.tags.is_empty() {
-        tags_include.extend(
-            query
-                .tags
-                .clone()
-                .into_iter()
-                .filter(|x| !x.starts_with('-')),
-        );
-        tags_exclude.extend(
-            query
-                .tags
-                .clone()
-                .into_iter()
-                .filter(|x| x.starts_with('-'))
-                .map(|t| t.replacen("-", "", 1)),
-        );
+    let query = SearchQueryJson::from(query.0);
+    Ok((
+        StatusCode::OK,
+        Json(run_search_json(&query, &sort, pagination.0, &state.conn).await?),
+    ))
+}
+
+/// A date field of a JSON search, naming the field if it can't be read
+fn json_date(
+    field: &str,
+    value: &Option<String>,
+) -> Result<Option<chrono::DateTime<chrono::Utc>>, AppError> {
+    value
+        .as_deref()
+        .map(query::date)
+        .transpose()
+        .map_err(|e| BadRequest(format!("{}: {}", field, e.error)))
+}
+
+/// The expression for a JSON search, from its query string if it has one, limited by its dates
+/// and text search
+pub(crate) fn json_query_expr(query: &SearchQueryJson) -> Result<Expr, AppError> {
+    let created = DateRange {
+        after: json_date("created_after", &query.created_after)?,
+        before: json_date("created_before", &query.created_before)?,
+    };
+    let uploaded = DateRange {
+        after: json_date("uploaded_after", &query.uploaded_after)?,
+        before: json_date("uploaded_before", &query.uploaded_before)?,
+    };
+
+    let expr = match &query.q {
+        Some(q) => query::parse(q),
+        None => query::from_lists(
+            query.tags.as_deref(),
+            query.creators.as_deref(),
+            query.collections.as_deref(),
+            query.tag_match,
+        ),
     }
+    .map_err(AppError::QueryParse)?;
 
-    creators_include.extend(query.creators.iter().map(|i| i.to_string()));
+    let mut all = vec![expr];
+    if created.after.is_some() || created.before.is_some() {
+        all.push(Expr::Term(Term::Created(created)));
+    }
+    if uploaded.after.is_some() || uploaded.before.is_some() {
+        all.push(Expr::Term(Term::Uploaded(uploaded)));
+    }
+    if let Some(description) = query.description.as_ref().filter(|d| !d.trim().is_empty()) {
+        all.push(Expr::Term(Term::Text(description.clone())));
+    }
+    let expr = if all.len() == 1 {
+        all.remove(0)
+    } else {
+        Expr::And(all)
+    };
+    let expr = if query.recursive {
+        expr.with_sub_collections()
+    } else {
+        expr
+    };
+    Ok(if query.implied {
+        expr.with_implied_tags()
+    } else {
+        expr
+    })
+}
 
-    let r = sqlx::query_file_scalar!(
-        "sql/endpoints/search/search.sqlx",
-        &creators_include[..],
-        &creators_exclude[..],
-        false,
-        &collections_include[..],
-        &collections_exclude[..],
-        false,
-        &tags_include[..],
-        &tags_exclude[..],
-        false,
-        pagination.per_page.unwrap_or(20).cast_signed(),
-        pagination.last.unwrap_or(0).cast_signed(),
-    )
-    .fetch_all(&state.conn)
-    .await?;
+/// Widen `collection:` terms naming smart collections to also match what their saved searches
+/// find, refusing smart collections whose searches lead back to themselves
+pub(crate) async fn expand_smart_collections(
+    expr: &Expr,
+    db: &sqlx::PgPool,
+) -> Result<Expr, AppError> {
+    let mut conn = db.acquire().await?;
+    expand(expr, Vec::new(), &mut conn).await
+}
 
-    let perceptual_hash: Option<BitVec> = None;
+fn expand<'a>(
+    expr: &'a Expr,
+    seen: Vec<i64>,
+    db: &'a mut PgConnection,
+) -> Pin<Box<dyn Future<Output = Result<Expr, AppError>> + Send + 'a>> {
+    Box::pin(async move {
+        Ok(match expr {
+            Expr::And(items) | Expr::Or(items) => {
+                let mut expanded = Vec::with_capacity(items.len());
+                for item in items {
+                    expanded.push(expand(item, seen.clone(), &mut *db).await?);
+                }
+                match expr {
+                    Expr::And(_) => Expr::And(expanded),
+                    _ => Expr::Or(expanded),
+                }
+            }
+            Expr::Not(inner) => Expr::Not(Box::new(expand(inner, seen, db).await?)),
+            Expr::Term(Term::Collection(pattern) | Term::CollectionTree(pattern)) => {
+                let sub_collections = matches!(expr, Expr::Term(Term::CollectionTree(_)));
+                let smart = query::smart_collections(pattern, sub_collections, &mut *db).await?;
+                if smart.is_empty() {
+                    return Ok(expr.clone());
+                }
+                let mut any = vec![expr.clone()];
+                for (collection, saved_search) in smart {
+                    if seen.contains(&collection) {
+                        return Err(BadRequest(format!(
+                            "smart collection {} includes itself",
+                            collection
+                        )));
+                    }
+                    let saved = load_saved_search(saved_search, &mut *db).await?;
+                    let mut seen = seen.clone();
+                    seen.push(collection);
+                    any.push(expand(&json_query_expr(&saved.query)?, seen, &mut *db).await?);
+                }
+                Expr::Or(any)
+            }
+            Expr::Term(_) => expr.clone(),
+        })
+    })
+}
 
-    Ok((
-        StatusCode::OK,
-        Json(SearchResult {
-            result: sqlx::query_file_as!(
-                ApiMediaReturn,
-                "sql/media_item_get.sqlx",
-                &r[..],
-                perceptual_hash,
-            )
-            .fetch_all(&state.conn)
-            .await?,
-            ..Default::default()
-        }),
-    ))
+/// Refuse to back a collection with a saved search that leads back to the collection. Takes a
+/// connection so it can see a collection made smart earlier in the same transaction
+pub(crate) async fn check_smart_collection(
+    collection: i64,
+    saved_search: i64,
+    db: &mut PgConnection,
+) -> Result<(), AppError> {
+    let saved = load_saved_search(saved_search, &mut *db).await?;
+    expand(&json_query_expr(&saved.query)?, vec![collection], db).await?;
+    Ok(())
 }
 
+/// The media of a smart collection, in its saved search's order
+pub(crate) async fn smart_collection_media(
+    saved_search: i64,
+    db: &sqlx::PgPool,
+) -> Result<Vec<i64>, AppError> {
+    let saved = load_saved_search(saved_search, db).await?;
+    let expr = expand_smart_collections(&json_query_expr(&saved.query)?, db).await?;
+    Ok(query::search_ids(&expr, &saved.sort, i64::MAX, 0, db).await?)
+}
+
+/// A page of media matching an expression, with snippets for any text search and facets if asked
 async fn query_media(
-    tags: Option<Vec<String>>,
-    creators: Option<Vec<String>>,
-    collections: Option<Vec<String>>,
+    expr: &Expr,
+    sort: &Sort,
     pagination: Pagination,
+    facets: Option<u32>,
     db: &sqlx::PgPool,
-) -> Result<Vec<ApiMediaReturn>, AppError> {
-    let mut collections_include: Vec<String> = Vec::new();
-    let mut collections_exclude: Vec<String> = Vec::new();
-    let mut no_collections: bool = false;
-    let mut creators_include: Vec<String> = Vec::new();
-    let mut creators_exclude: Vec<String> = Vec::new();
-    let mut no_creators: bool = false;
-    let mut tags_include: Vec<String> = Vec::new();
-    let mut tags_exclude: Vec<String> = Vec::new();
-    let mut no_tags: bool = false;
-
-    if let Some(collections) = collections {
-        collections_include.extend(
-            collections
-                .iter()
-                .filter(|i| !i.starts_with("-"))
-                .map(|i| i.to_string()),
-        );
-        collections_exclude.extend(
-            collections
-                .iter()
-                .filter(|i| i.starts_with("-"))
-                .map(|i| i.to_string()),
-        );
-    } else {
-        no_collections = true;
+) -> Result<SearchResult, AppError> {
+    let expr = &expand_smart_collections(expr, db).await?;
+    if sort.by(expr) == SortBy::Relevance && expr.text_queries().is_empty() {
+        return Err(BadRequest("relevance order needs a text search".to_string()));
     }
 
-    if let Some(creators) = creators {
-        creators_include.extend(
-            creators
-                .iter()
-                .filter(|i| !i.starts_with("-"))
-                .map(|i| i.to_string()),
-        );
-        creators_exclude.extend(
-            creators
-                .iter()
-                .filter(|i| i.starts_with("-"))
-                .map(|i| i.to_string()),
+    // Report each item's distance from the item they are ordered by
+    let mut perceptual_hash: Option<BitVec> = None;
+    if sort.by(expr) == SortBy::Similarity {
+        let Some(similar_to) = sort.similar_to else {
+            return Err(BadRequest("similarity order needs similar_to".to_string()));
+        };
+        perceptual_hash = Some(
+            sqlx::query_scalar!("SELECT perceptual_hash FROM media WHERE id = $1", similar_to)
+                .fetch_optional(db)
+                .await?
+                .ok_or(NotFound(format!("media {} not found", similar_to)))?,
         );
-    } else {
-        no_creators = true;
     }
 
-    if let Some(tags) = tags {
-        tags_include.extend(
-            tags.iter()
-                .filter(|i| !i.starts_with("-"))
-                .map(|i| i.to_string()),
-        );
-        tags_exclude.extend(
-            tags.iter()
-                .filter(|i| i.starts_with("-"))
-                .map(|i| i.to_string()),
-        );
-    } else {
-        no_tags = true;
-    }
-    let r = sqlx::query_file_scalar!(
-        "sql/endpoints/search/search.sqlx",
-        &creators_include[..],
-        &creators_exclude[..],
-        no_creators,
-        &collections_include[..],
-        &collections_exclude[..],
-        no_collections,
-        &tags_include[..],
-        &tags_exclude[..],
-        no_tags,
-        pagination.per_page.unwrap_or(20).cast_signed(),
-        pagination.last.unwrap_or(0).cast_signed(),
+    let cursor = pagination.cursor(&sort.label(expr))?;
+    let (r, next_cursor) = query::search_page(
+        expr,
+        sort,
+        pagination.limit(),
+        pagination.offset(),
+        cursor.as_ref(),
+        db,
     )
-    .fetch_all(db)
     .await?;
+    let total = match pagination.total {
+        Some(total) => Some(query::count(expr, total, db).await?),
+        None => None,
+    };
 
-    let perceptual_hash: Option<BitVec> = None;
-    Ok(sqlx::query_file_as!(
+    let result = sqlx::query_file_as!(
         ApiMediaReturn,
         "sql/media_item_get.sqlx",
         &r[..],
         perceptual_hash,
     )
     .fetch_all(db)
-    .await?)
+    .await?;
+
+    let facets = match facets {
+        Some(limit) => Some(query::facets(expr, limit.into(), db).await?),
+        None => None,
+    };
+    let snippets = query::snippets(expr, &r, db).await?;
+    Ok(SearchResult {
+        result,
+        snippets: (!snippets.is_empty()).then_some(snippets),
+        next_cursor,
+        total,
+        facets,
+        ..Default::default()
+    })
 }
 
+/// Order of collection search results, for their cursors
+const COLLECTION_ORDER: &str = "collection id";
+
+/// A page of collections, and the cursor for the next page if there is one
 async fn query_collections(
     tags: Option<Vec<String>>,
     creators: Option<Vec<String>>,
+    tag_match: TagMatch,
     pagination: Pagination,
     db: &sqlx::PgPool,
-) -> Result<Vec<ApiCollectionResult>, AppError> {
+) -> Result<(Vec<ApiCollectionResult>, Option<String>), AppError> {
     let mut creators_include: Vec<String> = Vec::new();
     let mut creators_exclude: Vec<String> = Vec::new();
     let mut tags_include: Vec<String> = Vec::new();
@@ -195,7 +262,7 @@ async fn query_collections(
         creators_exclude.extend(
             creators
                 .iter()
-                .filter(|i| i.starts_with("-"))
+                .filter_map(|i| i.strip_prefix("-"))
                 .map(|i| i.to_string()),
         );
     }
@@ -208,86 +275,148 @@ async fn query_collections(
         );
         tags_exclude.extend(
             tags.iter()
-                .filter(|i| i.starts_with("-"))
+                .filter_map(|i| i.strip_prefix("-"))
                 .map(|i| i.to_string()),
         );
     }
 
-    Ok(sqlx::query_file_as!(
+    let cursor = pagination.cursor(COLLECTION_ORDER)?;
+    let mut found = sqlx::query_file_as!(
         ApiCollectionResult,
         "sql/endpoints/search/collection.sqlx",
         &creators_include[..],
         &creators_exclude[..],
         &tags_include[..],
         &tags_exclude[..],
-        pagination.per_page.unwrap_or(20).cast_signed(),
-        pagination.last.unwrap_or(0).cast_signed()
+        pagination.limit().saturating_add(1),
+        pagination.offset(),
+        tag_match == TagMatch::All,
+        cursor.map(|c| c.id)
     )
     .fetch_all(db)
-    .await?)
+    .await?;
+
+    let (ids, next_cursor) = Cursor::page(
+        found.iter().filter_map(|c| c.id.map(|id| (id, None))).collect(),
+        pagination.limit(),
+        COLLECTION_ORDER,
+    );
+    found.truncate(ids.len());
+    Ok((found, next_cursor))
 }
 
-#[utoipa::path(post, path = "/v1/search", params(Pagination), request_body = SearchQueryJson, responses((status = OK, body = SearchResult)), tags = ["search"]
+#[utoipa::path(post, path = "/v1/search", params(Pagination, Sort), request_body = SearchQueryJson, responses((status = OK, body = SearchResult), (status = BAD_REQUEST, body = QueryError)), tags = ["search"]
 )]
 pub async fn search_query_json(
     state: State<AppState>,
     pagination: Query<Pagination>,
+    sort: Query<Sort>,
     query: Json<SearchQueryJson>,
 ) -> Result<(StatusCode, Json<SearchResult>), AppError> {
-    let mut media: Vec<ApiMediaReturn> = vec![];
-    let mut collections: Option<Vec<ApiCollectionResult>> = None;
-    dbg!(&query);
-    match query.query_type {
-        QueryType::All => {
-            media = query_media(
-                query.tags.clone(),
-                query.creators.clone(),
-                query.collections.clone(),
-                pagination.0.clone(),
-                &state.conn,
-            )
-            .await?;
-            collections = Some(
-                query_collections(
-                    query.tags.clone(),
-                    query.creators.clone(),
-                    pagination.0.clone(),
-                    &state.conn,
-                )
-                .await?,
-            )
+    Ok((
+        StatusCode::OK,
+        Json(run_search_json(&query, &sort, pagination.0, &state.conn).await?),
+    ))
+}
+
+/// Whether a JSON search is made only of tag and creator lists, without a query string, text or
+/// dates
+fn lists_only(query: &SearchQueryJson) -> bool {
+    query.q.is_none()
+        && query.description.as_ref().is_none_or(|d| d.trim().is_empty())
+        && query.created_after.is_none()
+        && query.created_before.is_none()
+        && query.uploaded_after.is_none()
+        && query.uploaded_before.is_none()
+}
+
+/// A page of the results of a JSON search
+pub(crate) async fn run_search_json(
+    query: &SearchQueryJson,
+    sort: &Sort,
+    pagination: Pagination,
+    db: &sqlx::PgPool,
+) -> Result<SearchResult, AppError> {
+    let mut result = SearchResult::default();
+    let expr = json_query_expr(query)?;
+    // A search for both continues whichever list its cursor came from
+    let (media, collections) = match query.query_type {
+        QueryType::All if pagination.cursor.is_some() => {
+            let collections = pagination.cursor(COLLECTION_ORDER).is_ok();
+            (!collections, collections)
         }
-        QueryType::Media => {
-            media = query_media(
-                query.tags.clone(),
-                query.creators.clone(),
-                query.collections.clone(),
-                pagination.0,
-                &state.conn,
-            )
-            .await?;
+        QueryType::All => (true, true),
+        QueryType::Media => (true, false),
+        QueryType::Collection => (false, true),
+    };
+    if media {
+        result = query_media(&expr, sort, pagination.clone(), query.facets, db).await?;
+    }
+    if collections && !lists_only(query) {
+        // Collections are matched by the tag and creator lists alone, so they can't meet the rest
+        result.collections = Some(Vec::new());
+    } else if collections {
+        let (found, next_cursor) = query_collections(
+            query.tags.clone(),
+            query.creators.clone(),
+            query.tag_match,
+            pagination,
+            db,
+        )
+        .await?;
+        result.collections = Some(found);
+        result.collections_next_cursor = next_cursor;
+    }
+    Ok(result)
+}
+
+/// Most items a random request returns
+const MAX_RANDOM: u32 = 100;
+
+/// Rounds of picks before settling for fewer random items than asked for
+const RANDOM_ROUNDS: usize = 3;
+
+/// Random media matching the same filters as a search, for a screensaver or spot checks
+#[utoipa::path(get, path = "/v1/media/random", params(SearchQuery, RandomQuery), responses((status = OK, body = SearchResult), (status = BAD_REQUEST, body = QueryError)), tags = ["media"]
+)]
+pub async fn random_media(
+    state: State<AppState>,
+    query: Query<SearchQuery>,
+    random: Query<RandomQuery>,
+) -> Result<Json<SearchResult>, AppError> {
+    let count = random.count.unwrap_or(1).clamp(1, MAX_RANDOM) as usize;
+    let expr = expand_smart_collections(
+        &json_query_expr(&SearchQueryJson::from(query.0))?,
+        &state.conn,
+    )
+    .await?;
+
+    // Picks can land on the same item, so top up a few times
+    let mut ids: Vec<i64> = Vec::new();
+    for _ in 0..RANDOM_ROUNDS {
+        if ids.len() >= count {
+            break;
         }
-        QueryType::Collection => {
-            collections = Some(
-                query_collections(
-                    query.tags.clone(),
-                    query.creators.clone(),
-                    pagination.0.clone(),
-                    &state.conn,
-                )
-                .await?,
-            )
+        for id in query::random_ids(&expr, (count - ids.len()) as i64, &ids, &state.conn).await? {
+            if ids.len() < count && !ids.contains(&id) {
+                ids.push(id);
+            }
         }
     }
-    //
-    Ok((
-        StatusCode::OK,
-        Json(SearchResult {
-            result: media,
-            collections,
-            ..Default::default()
-        }),
-    ))
+
+    let perceptual_hash: Option<BitVec> = None;
+    let result = sqlx::query_file_as!(
+        ApiMediaReturn,
+        "sql/media_item_get.sqlx",
+        &ids[..],
+        perceptual_hash,
+    )
+    .fetch_all(&state.conn)
+    .await?;
+    Ok(Json(SearchResult {
+        result,
+        ..Default::default()
+    }))
 }
 
 #[utoipa::path(get, path = "/v1/search/hash", params(HashQuery, Pagination), responses((status = OK, body = SearchResult)), tags = ["search"]
@@ -297,15 +426,41 @@ pub async fn hash_search(
     query: Query<HashQuery>,
     pagination: Query<Pagination>,
 ) -> Result<Json<SearchResult>, AppError> {
-    let r = sqlx::query_file_scalar!(
-        "sql/endpoints/search/hash_search.sqlx",
-        BitVec::from_bytes(&query.hash.to_be_bytes()),
-        query.max_distance.unwrap_or(3) as f64,
-        pagination.per_page.unwrap_or(20).cast_signed(),
-        pagination.last.unwrap_or(0).cast_signed()
-    )
-    .fetch_all(&state.conn)
-    .await?;
+    let order = format!("hash {} {}", query.hash, query.max_distance.unwrap_or(3));
+    let cursor = pagination.cursor(&order)?;
+    let (r, next_cursor) = Cursor::page(
+        sqlx::query_file!(
+            "sql/endpoints/search/hash_search.sqlx",
+            BitVec::from_bytes(&query.hash.to_be_bytes()),
+            query.max_distance.unwrap_or(3) as f64,
+            pagination.limit().saturating_add(1),
+            pagination.offset(),
+            cursor
+                .as_ref()
+                .and_then(|c| c.key.as_ref())
+                .and_then(|k| k.parse::<f64>().ok()),
+            cursor.as_ref().map(|c| c.id)
+        )
+        .fetch_all(&state.conn)
+        .await?
+        .into_iter()
+        .map(|r| (r.id, r.distance.map(|d| d.to_string())))
+        .collect(),
+        pagination.limit(),
+        &order,
+    );
+    let total = match pagination.total {
+        Some(_) => Some(
+            sqlx::query_scalar!(
+                r#"SELECT COUNT(*) AS "count!" FROM media WHERE perceptual_hash <~> $1::bit(64) < $2"#,
+                BitVec::from_bytes(&query.hash.to_be_bytes()),
+                query.max_distance.unwrap_or(3) as f64,
+            )
+            .fetch_one(&state.conn)
+            .await?,
+        ),
+        None => None,
+    };
 
     let found_media = sqlx::query_file_as!(
         ApiMediaReturn,
@@ -318,6 +473,8 @@ pub async fn hash_search(
 
     Ok(Json(SearchResult {
         result: found_media,
+        next_cursor,
+        total,
         ..Default::default()
     }))
 }
@@ -1,5 +1,6 @@
 use sqlx::{Postgres, Transaction};
 use crate::error::AppError;
+use std::collections::HashMap;
 
 pub async fn creators_create(
     creators_in: Vec<String>,
@@ -40,4 +41,119 @@ pub async fn creators_create(
         //Merge the newly created creators, and the existing creator ids
         creators_inserted.extend(existing_id);
         Ok(creators_inserted)
-}
\ No newline at end of file
+}
+
+/// Swap tag aliases for the tags they stand for, keeping the order. Names are expected in lower
+/// case, as tags are stored
+pub async fn tags_resolve_aliases(
+    tags: Vec<String>,
+    db: &mut Transaction<'_, Postgres>,
+) -> Result<Vec<String>, AppError> {
+    let aliases: HashMap<String, String> = sqlx::query!(
+        r#"SELECT tag_alias.alias, tags.tag
+           FROM tag_alias
+                    JOIN tags ON tags.id = tag_alias.tag
+           WHERE tag_alias.alias = ANY ($1::varchar[])"#,
+        &tags[..]
+    )
+    .fetch_all(&mut **db)
+    .await?
+    .into_iter()
+    .map(|i| (i.alias, i.tag))
+    .collect();
+    Ok(tags
+        .into_iter()
+        .map(|t| aliases.get(&t).cloned().unwrap_or(t))
+        .collect())
+}
+
+/// The tags given along with every tag they imply, directly or through others
+pub async fn tags_implied(
+    tags: Vec<i64>,
+    db: &mut Transaction<'_, Postgres>,
+) -> Result<Vec<i64>, AppError> {
+    Ok(sqlx::query_scalar!(
+        r#"WITH RECURSIVE implied AS (SELECT UNNEST($1::bigint[]) AS id
+                                      UNION
+                                      SELECT tag_implications.implies
+                                      FROM tag_implications
+                                               JOIN implied ON tag_implications.tag = implied.id)
+           SELECT id AS "id!" FROM implied"#,
+        &tags[..]
+    )
+    .fetch_all(&mut **db)
+    .await?)
+}
+
+/// Bump the version of every media item tagged with any of the tags, as a change to the tags
+/// themselves is about to reach them. Returns their ids, for their sidecars to be rewritten once
+/// the change is committed
+pub async fn tags_touch_media(
+    tags: &[i64],
+    db: &mut Transaction<'_, Postgres>,
+) -> Result<Vec<i64>, AppError> {
+    Ok(sqlx::query_scalar!(
+        r#"UPDATE media
+           SET version = version + 1
+           WHERE id IN (SELECT media_id FROM media_tags WHERE tag_id = ANY ($1::bigint[]))
+           RETURNING id"#,
+        tags
+    )
+    .fetch_all(&mut **db)
+    .await?)
+}
+
+/// Bump the version of the media given, as a change made elsewhere reaches them. Returns the ids of
+/// those that exist, for their sidecars to be rewritten once the change is committed
+pub async fn media_touch(
+    media: &[i64],
+    db: &mut Transaction<'_, Postgres>,
+) -> Result<Vec<i64>, AppError> {
+    Ok(sqlx::query_scalar!(
+        r#"UPDATE media SET version = version + 1 WHERE id = ANY ($1::bigint[]) RETURNING id"#,
+        media
+    )
+    .fetch_all(&mut **db)
+    .await?)
+}
+
+/// Bump the version of every media item in the collection or any collection below it, as their
+/// paths are about to change
+pub async fn collection_touch_media(
+    collection: i64,
+    db: &mut Transaction<'_, Postgres>,
+) -> Result<Vec<i64>, AppError> {
+    Ok(sqlx::query_scalar!(
+        r#"WITH RECURSIVE tree AS (SELECT $1::bigint AS id
+                                   UNION
+                                   SELECT collections.id
+                                   FROM collections
+                                            JOIN tree ON collections.parent = tree.id)
+           UPDATE media
+           SET version = version + 1
+           WHERE id IN (SELECT media_id
+                        FROM media_collection
+                        WHERE collection_id IN (SELECT id FROM tree))
+           RETURNING id"#,
+        collection
+    )
+    .fetch_all(&mut **db)
+    .await?)
+}
+
+/// Bump the version of every media item by any of the creators, as a change to the creators
+/// themselves is about to reach them
+pub async fn creators_touch_media(
+    creators: &[i64],
+    db: &mut Transaction<'_, Postgres>,
+) -> Result<Vec<i64>, AppError> {
+    Ok(sqlx::query_scalar!(
+        r#"UPDATE media
+           SET version = version + 1
+           WHERE id IN (SELECT media_id FROM media_creators WHERE creator_id = ANY ($1::bigint[]))
+           RETURNING id"#,
+        creators
+    )
+    .fetch_all(&mut **db)
+    .await?)
+}
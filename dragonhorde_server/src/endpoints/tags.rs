@@ -1,9 +1,17 @@
+use crate::api_models::{
+    ApiTagAlias, ApiTagImplication, ApiTagMerge, ApiTagPatch, ApiTagResult, Cursor, Pagination,
+    TagAliasResults, TagImplicationResults, TagsDeleted, TagsResults,
+};
+use crate::backfill;
+use crate::endpoints::shared::tags_touch_media;
 use crate::error::AppError;
+use crate::error::AppError::{BadRequest, Exists, NotFound};
 use crate::AppState;
-use axum::extract::State;
+use axum::extract::{Path, State};
 use axum::Json;
 use axum_extra::extract::Query;
 use serde::Deserialize;
+use sqlx::types::chrono::FixedOffset;
 use utoipa::IntoParams;
 
 #[derive(IntoParams, Debug, Deserialize)]
@@ -36,4 +44,487 @@ pub async fn search_tags(
     Ok(
         Json(res),
     )
-}
\ No newline at end of file
+}
+
+pub(crate) struct FoundTag {
+    pub(crate) id: i64,
+    pub(crate) tag: String,
+}
+
+/// A tag by its name or one of its aliases
+pub(crate) async fn find_tag(
+    name: &str,
+    db: impl sqlx::PgExecutor<'_>,
+) -> Result<FoundTag, AppError> {
+    let name = name.trim().to_lowercase();
+    sqlx::query_as!(
+        FoundTag,
+        r#"SELECT tags.id, tags.tag
+           FROM tags
+           WHERE tags.tag = $1
+              OR tags.id = (SELECT tag_alias.tag FROM tag_alias WHERE tag_alias.alias = $1)"#,
+        name
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(NotFound(format!("tag {} not found", name)))
+}
+
+#[utoipa::path(get, path = "/v1/tags/aliases", responses((status = OK, body = TagAliasResults)), tags = ["tags"])]
+pub async fn get_tag_aliases(state: State<AppState>) -> Result<Json<TagAliasResults>, AppError> {
+    let result = sqlx::query_as!(
+        ApiTagAlias,
+        r#"SELECT tag_alias.alias, tags.tag
+           FROM tag_alias
+                    JOIN tags ON tags.id = tag_alias.tag
+           ORDER BY tag_alias.alias"#
+    )
+    .fetch_all(&state.conn)
+    .await?;
+    Ok(Json(TagAliasResults { result }))
+}
+
+/// Make one name stand for a tag. If the alias is already a tag in its own right, everything
+/// tagged with it is moved over to the tag, along with its own aliases, and it is removed
+#[utoipa::path(post, path = "/v1/tags/aliases", request_body = ApiTagAlias, responses((status = OK, body = ApiTagAlias)), tags = ["tags"])]
+pub async fn post_tag_alias(
+    state: State<AppState>,
+    Json(payload): Json<ApiTagAlias>,
+) -> Result<Json<ApiTagAlias>, AppError> {
+    let alias = payload.alias.trim().to_lowercase();
+    let tag = payload.tag.trim().to_lowercase();
+    if alias.is_empty() || alias == tag {
+        return Err(BadRequest(format!("{} can't be an alias of {}", alias, tag)));
+    }
+
+    let mut tx = state.conn.begin().await?;
+    if sqlx::query_scalar!("SELECT id FROM tag_alias WHERE alias = $1", alias)
+        .fetch_optional(&mut *tx)
+        .await?
+        .is_some()
+    {
+        return Err(Exists(format!("tag alias {} already exists", alias)));
+    }
+    // An alias of an alias stands for the same tag
+    let target = find_tag(&tag, &mut *tx).await?;
+    if target.tag == alias {
+        return Err(BadRequest(format!("{} is already an alias of {}", tag, alias)));
+    }
+
+    let mut touched: Vec<i64> = Vec::new();
+    if let Some(existing) = sqlx::query_scalar!("SELECT id FROM tags WHERE tag = $1", alias)
+        .fetch_optional(&mut *tx)
+        .await?
+    {
+        touched = tags_touch_media(&[existing], &mut tx).await?;
+        sqlx::query!("CALL merge_tags($1, $2)", existing, target.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    sqlx::query!(
+        "INSERT INTO tag_alias (tag, alias) VALUES ($1, $2)",
+        target.id,
+        alias
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    state
+        .sidecars
+        .sync(&state.storage_dir, &touched, &state.conn)
+        .await?;
+
+    Ok(Json(ApiTagAlias {
+        alias,
+        tag: target.tag,
+    }))
+}
+
+#[utoipa::path(delete, path = "/v1/tags/aliases/{alias}", responses((status = OK)), tags = ["tags"])]
+pub async fn delete_tag_alias(
+    state: State<AppState>,
+    Path(alias): Path<String>,
+) -> Result<(), AppError> {
+    let result = sqlx::query!(
+        "DELETE FROM tag_alias WHERE alias = $1",
+        alias.to_lowercase()
+    )
+    .execute(&state.conn)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(NotFound(format!("tag alias {} not found", alias)));
+    }
+    Ok(())
+}
+
+#[utoipa::path(get, path = "/v1/tags/implications", responses((status = OK, body = TagImplicationResults)), tags = ["tags"])]
+pub async fn get_tag_implications(
+    state: State<AppState>,
+) -> Result<Json<TagImplicationResults>, AppError> {
+    let result = sqlx::query_as!(
+        ApiTagImplication,
+        r#"SELECT tag.tag, implies.tag AS implies
+           FROM tag_implications
+                    JOIN tags tag ON tag.id = tag_implications.tag
+                    JOIN tags implies ON implies.id = tag_implications.implies
+           ORDER BY tag.tag, implies.tag"#
+    )
+    .fetch_all(&state.conn)
+    .await?;
+    Ok(Json(TagImplicationResults { result }))
+}
+
+/// Make one tag imply another, refusing rules that would lead back to the first tag. Media that
+/// already have the tag are given the implied tags in the background
+#[utoipa::path(post, path = "/v1/tags/implications", request_body = ApiTagImplication, responses((status = OK, body = ApiTagImplication)), tags = ["tags"])]
+pub async fn post_tag_implication(
+    state: State<AppState>,
+    Json(payload): Json<ApiTagImplication>,
+) -> Result<Json<ApiTagImplication>, AppError> {
+    let tag = find_tag(&payload.tag, &state.conn).await?;
+    let implies = find_tag(&payload.implies, &state.conn).await?;
+    if tag.id == implies.id {
+        return Err(BadRequest(format!("{} can't imply itself", tag.tag)));
+    }
+    if sqlx::query_scalar!(
+        r#"WITH RECURSIVE implied AS (SELECT $1::bigint AS id
+                                      UNION
+                                      SELECT tag_implications.implies
+                                      FROM tag_implications
+                                               JOIN implied ON tag_implications.tag = implied.id)
+           SELECT EXISTS (SELECT 1 FROM implied WHERE id = $2) AS "cycle!""#,
+        implies.id,
+        tag.id
+    )
+    .fetch_one(&state.conn)
+    .await?
+    {
+        return Err(BadRequest(format!(
+            "{} already implies {}",
+            implies.tag, tag.tag
+        )));
+    }
+
+    let inserted = sqlx::query!(
+        "INSERT INTO tag_implications (tag, implies) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        tag.id,
+        implies.id
+    )
+    .execute(&state.conn)
+    .await?;
+    if inserted.rows_affected() == 0 {
+        return Err(Exists(format!("{} already implies {}", tag.tag, implies.tag)));
+    }
+
+    let (state, tag_id) = (state.0.clone(), tag.id);
+    tokio::spawn(async move {
+        let result = async {
+            let changed = backfill::tag_implications(Some(tag_id), &state.conn).await?;
+            state
+                .sidecars
+                .sync(&state.storage_dir, &changed, &state.conn)
+                .await
+        }
+        .await;
+        if let Err(e) = result {
+            tracing::error!("failed to apply implications of tag {}: {e}", tag_id);
+        }
+    });
+    Ok(Json(ApiTagImplication {
+        tag: tag.tag,
+        implies: implies.tag,
+    }))
+}
+
+#[utoipa::path(delete, path = "/v1/tags/implications/{tag}/{implies}", responses((status = OK)), tags = ["tags"])]
+pub async fn delete_tag_implication(
+    state: State<AppState>,
+    Path((tag, implies)): Path<(String, String)>,
+) -> Result<(), AppError> {
+    let tag = find_tag(&tag, &state.conn).await?;
+    let implies = find_tag(&implies, &state.conn).await?;
+    let result = sqlx::query!(
+        "DELETE FROM tag_implications WHERE tag = $1 AND implies = $2",
+        tag.id,
+        implies.id
+    )
+    .execute(&state.conn)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(NotFound(format!(
+            "{} doesn't imply {}",
+            tag.tag, implies.tag
+        )));
+    }
+    Ok(())
+}
+
+async fn load_tag(id: i64, db: &sqlx::PgPool) -> Result<ApiTagResult, AppError> {
+    let no_group: Option<String> = None;
+    sqlx::query_file_as!(
+        ApiTagResult,
+        "sql/endpoints/tags/get_tags.sqlx",
+        &vec![id][..],
+        no_group
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(NotFound(format!("tag {} not found", id)))
+}
+
+#[derive(IntoParams, Debug, Deserialize)]
+pub struct TagListQuery {
+    /// Only tags in this group
+    group: Option<String>,
+}
+
+/// Order of tag listings, for their cursors
+const TAG_ORDER: &str = "tag name";
+
+/// Every tag by name with how often it is used, or a page of them when per_page, last or cursor
+/// is given
+#[utoipa::path(get, path = "/v1/tags/list", params(TagListQuery, Pagination), responses((status = OK, body = TagsResults)), tags = ["tags"])]
+pub async fn get_tags(
+    state: State<AppState>,
+    query: Query<TagListQuery>,
+    pagination: Query<Pagination>,
+) -> Result<Json<TagsResults>, AppError> {
+    let total = match pagination.total {
+        Some(_) => Some(
+            sqlx::query_scalar!(
+                r#"SELECT COUNT(*) AS "count!"
+                   FROM tags
+                            LEFT JOIN tag_groups ON tag_groups.id = tags."group"
+                   WHERE $1::text IS NULL OR tag_groups.name = $1"#,
+                query.group
+            )
+            .fetch_one(&state.conn)
+            .await?,
+        ),
+        None => None,
+    };
+    if !pagination.is_paged() {
+        let tags = sqlx::query_file_as!(
+            ApiTagResult,
+            "sql/endpoints/tags/get_tags.sqlx",
+            &vec![][..],
+            query.group
+        )
+        .fetch_all(&state.conn)
+        .await?;
+        return Ok(Json(TagsResults {
+            result: tags,
+            next_cursor: None,
+            total,
+        }));
+    }
+
+    let cursor = pagination.cursor(TAG_ORDER)?;
+    let (ids, next_cursor) = Cursor::page(
+        sqlx::query!(
+            r#"SELECT tags.id, tags.tag
+               FROM tags
+                        LEFT JOIN tag_groups ON tag_groups.id = tags."group"
+               WHERE ($1::text IS NULL OR tag_groups.name = $1)
+                 AND ($2::text IS NULL OR (tags.tag, tags.id) > ($2::text, $3::bigint))
+               ORDER BY tags.tag, tags.id
+               LIMIT $4 OFFSET $5"#,
+            query.group,
+            cursor.as_ref().and_then(|c| c.key.clone()),
+            cursor.as_ref().map(|c| c.id),
+            pagination.limit().saturating_add(1),
+            pagination.offset()
+        )
+        .fetch_all(&state.conn)
+        .await?
+        .into_iter()
+        .map(|r| (r.id, Some(r.tag)))
+        .collect(),
+        pagination.limit(),
+        TAG_ORDER,
+    );
+    // No ids would read as every tag
+    if ids.is_empty() {
+        return Ok(Json(TagsResults {
+            result: Vec::new(),
+            next_cursor,
+            total,
+        }));
+    }
+
+    let no_group: Option<String> = None;
+    let tags = sqlx::query_file_as!(
+        ApiTagResult,
+        "sql/endpoints/tags/get_tags.sqlx",
+        &ids[..],
+        no_group
+    )
+    .fetch_all(&state.conn)
+    .await?;
+    Ok(Json(TagsResults {
+        result: tags,
+        next_cursor,
+        total,
+    }))
+}
+
+#[utoipa::path(get, path = "/v1/tags/{id}", responses((status = OK, body = ApiTagResult)), tags = ["tags"])]
+pub async fn get_tag(
+    state: State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<ApiTagResult>, AppError> {
+    Ok(Json(load_tag(id, &state.conn).await?))
+}
+
+/// Rename a tag, move it to another group or describe it. A new name that is already taken by
+/// another tag, or one of its aliases, merges this tag into that one, and any other changes apply
+/// to the merged tag
+#[utoipa::path(patch, path = "/v1/tags/{id}", request_body = ApiTagPatch, responses((status = OK, body = ApiTagResult)), tags = ["tags"])]
+pub async fn patch_tag(
+    state: State<AppState>,
+    Path(id): Path<i64>,
+    Json(payload): Json<ApiTagPatch>,
+) -> Result<Json<ApiTagResult>, AppError> {
+    let current = load_tag(id, &state.conn).await?;
+    let mut id = current.id;
+    let mut tx = state.conn.begin().await?;
+    // Names and groups show on every item tagged with it, descriptions don't
+    let touched = if payload.tag.is_some() || payload.group.is_some() {
+        tags_touch_media(&[id], &mut tx).await?
+    } else {
+        Vec::new()
+    };
+
+    if let Some(name) = payload.tag.map(|t| t.trim().to_lowercase()) {
+        if name.is_empty() {
+            return Err(BadRequest("a tag needs a name".to_string()));
+        }
+        if name != current.tag {
+            match find_tag(&name, &mut *tx).await {
+                Ok(other) if other.id != id => {
+                    sqlx::query!("CALL merge_tags($1, $2)", id, other.id)
+                        .execute(&mut *tx)
+                        .await?;
+                    id = other.id;
+                }
+                // One of its own aliases, which becomes its name
+                Ok(_) => {
+                    sqlx::query!("DELETE FROM tag_alias WHERE alias = $1", name)
+                        .execute(&mut *tx)
+                        .await?;
+                    sqlx::query!("UPDATE tags SET tag = $2 WHERE id = $1", id, name)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                Err(NotFound(_)) => {
+                    sqlx::query!("UPDATE tags SET tag = $2 WHERE id = $1", id, name)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    if let Some(group) = payload.group.map(|g| g.trim().to_lowercase()) {
+        let group_id = sqlx::query_scalar!(
+            r#"WITH created AS (INSERT INTO tag_groups (name) VALUES ($1) ON CONFLICT (name) DO NOTHING RETURNING id)
+               SELECT id AS "id!" FROM created
+               UNION ALL
+               SELECT id FROM tag_groups WHERE name = $1"#,
+            group
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query!(r#"UPDATE tags SET "group" = $2 WHERE id = $1"#, id, group_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    if let Some(description) = payload.description {
+        sqlx::query!(
+            "UPDATE tags SET description = $2 WHERE id = $1",
+            id,
+            description
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    state
+        .sidecars
+        .sync(&state.storage_dir, &touched, &state.conn)
+        .await?;
+    Ok(Json(load_tag(id, &state.conn).await?))
+}
+
+/// Merge tags into another. Everything tagged with them is tagged with it instead, and their
+/// aliases and implications move over to it
+#[utoipa::path(post, path = "/v1/tags/merge", request_body = ApiTagMerge, responses((status = OK, body = ApiTagResult)), tags = ["tags"])]
+pub async fn merge_tags(
+    state: State<AppState>,
+    Json(payload): Json<ApiTagMerge>,
+) -> Result<Json<ApiTagResult>, AppError> {
+    let mut tx = state.conn.begin().await?;
+    let into = find_tag(&payload.into, &mut *tx).await?;
+    let mut touched: Vec<i64> = Vec::new();
+    for tag in &payload.tags {
+        let tag = find_tag(tag, &mut *tx).await?;
+        if tag.id != into.id {
+            touched.extend(tags_touch_media(&[tag.id], &mut tx).await?);
+            sqlx::query!("CALL merge_tags($1, $2)", tag.id, into.id)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+    tx.commit().await?;
+    touched.sort();
+    touched.dedup();
+    state
+        .sidecars
+        .sync(&state.storage_dir, &touched, &state.conn)
+        .await?;
+    Ok(Json(load_tag(into.id, &state.conn).await?))
+}
+
+/// Delete a tag, removing it from everything tagged with it
+#[utoipa::path(delete, path = "/v1/tags/{id}", responses((status = OK)), tags = ["tags"])]
+pub async fn delete_tag(state: State<AppState>, Path(id): Path<i64>) -> Result<(), AppError> {
+    let mut tx = state.conn.begin().await?;
+    let touched = tags_touch_media(&[id], &mut tx).await?;
+    let result = sqlx::query!("DELETE FROM tags WHERE id = $1", id)
+        .execute(&mut *tx)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(NotFound(format!("tag {} not found", id)));
+    }
+    tx.commit().await?;
+    state
+        .sidecars
+        .sync(&state.storage_dir, &touched, &state.conn)
+        .await?;
+    Ok(())
+}
+
+/// Delete every tag that nothing is tagged with, keeping those with aliases or implications. As no
+/// media have these tags, none of their versions or sidecars change
+#[utoipa::path(delete, path = "/v1/tags/unused", responses((status = OK, body = TagsDeleted)), tags = ["tags"])]
+pub async fn delete_unused_tags(state: State<AppState>) -> Result<Json<TagsDeleted>, AppError> {
+    let result = sqlx::query!(
+        r#"DELETE
+           FROM tags
+           WHERE NOT EXISTS (SELECT 1 FROM media_tags WHERE media_tags.tag_id = tags.id)
+             AND NOT EXISTS (SELECT 1 FROM collection_tags WHERE collection_tags.tag_id = tags.id)
+             AND NOT EXISTS (SELECT 1 FROM tag_alias WHERE tag_alias.tag = tags.id)
+             AND NOT EXISTS (SELECT 1
+                             FROM tag_implications
+                             WHERE tag_implications.tag = tags.id
+                                OR tag_implications.implies = tags.id)"#
+    )
+    .execute(&state.conn)
+    .await?;
+    Ok(Json(TagsDeleted {
+        deleted: result.rows_affected(),
+    }))
+}
@@ -1,6 +1,7 @@
 use axum::http::{header, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum_extra::headers::ContentType;
+use crate::query::QueryError;
 // pub struct AppError(anyhow::Error);
 
 pub enum AppError {
@@ -11,6 +12,9 @@ pub enum AppError {
     Forbidden(String),
     NotFound(String),
     Exists(String),
+    PreconditionFailed(String),
+    /// A search query that could not be parsed, reported with where the problem is
+    QueryParse(QueryError),
 }
 
 impl AppError {
@@ -45,6 +49,15 @@ impl IntoResponse for AppError {
             Self::Forbidden(s) => Self::into_json_response(StatusCode::FORBIDDEN, s),
             Self::NotFound(s) => Self::into_json_response(StatusCode::NOT_FOUND, s),
             Self::Exists(s) => {Self::into_json_response(StatusCode::CONFLICT, s)}
+            Self::PreconditionFailed(s) => {
+                Self::into_json_response(StatusCode::PRECONDITION_FAILED, s)
+            }
+            Self::QueryParse(e) => (
+                StatusCode::BAD_REQUEST,
+                [(header::CONTENT_TYPE, ContentType::json().to_string())],
+                serde_json::json!(e).to_string(),
+            )
+                .into_response(),
         }
     }
 }
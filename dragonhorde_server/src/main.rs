@@ -1,9 +1,16 @@
 mod endpoints;
 pub mod error;
 mod api_models;
+mod canonical;
+mod archive;
+mod sidecar;
+mod query;
+mod backfill;
 
 use axum::extract::DefaultBodyLimit;
+use clap::{Parser, Subcommand};
 use std::env;
+use std::sync::Arc;
 use sqlx::{Pool, Postgres};
 use sqlx::postgres::PgPoolOptions;
 use tokio::{self, net::TcpListener};
@@ -18,9 +25,51 @@ struct AppState {
     conn: Pool<Postgres>,
     storage_dir: std::path::PathBuf,
     thumbnail_dir: std::path::PathBuf,
+    canonicaliser: Arc<canonical::Canonicaliser>,
+    sidecars: sidecar::SidecarFormats,
 }
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the server. This is the default
+    Serve,
+    /// Write the library, or part of it, to an archive
+    Export {
+        archive: std::path::PathBuf,
+        /// Only export media in this collection, eg. comics/series
+        #[arg(long)]
+        collection: Option<String>,
+        /// Only export media with these tags
+        #[arg(long)]
+        tag: Vec<String>,
+        /// Only export media by these creators
+        #[arg(long)]
+        creator: Vec<String>,
+    },
+    /// Restore an archive into the library
+    Import { archive: std::path::PathBuf },
+    /// Rewrite the sidecar files of every media item, in the formats set by SIDECARS
+    Sidecars,
+    /// Record the file size of media stored before sizes were kept
+    FileSizes,
+    /// Add the tags implied by every media item's tags
+    TagImplications,
+    /// Rewrite every stored source to its canonical url, merging duplicates. Safe to run again
+    /// after adding new site rules
+    CanonicalSources,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
     // unsafe {
     //     env::set_var("RUST_LOG", "debug");
     // }
@@ -55,8 +104,80 @@ async fn main() -> anyhow::Result<()> {
         thumbnail_dir: env::var("THUMBNAILS")
             .expect("THUMBNAILS is not set in .env file")
             .parse()?,
+        canonicaliser: Arc::new(canonical::Canonicaliser::default()),
+        sidecars: env::var("SIDECARS")
+            .unwrap_or_default()
+            .parse()
+            .map_err(|e: String| anyhow::anyhow!(e))?,
     };
 
+    match cli.command {
+        None | Some(Command::Serve) => {}
+        Some(Command::Export {
+            archive: path,
+            collection,
+            tag,
+            creator,
+        }) => {
+            let filter = archive::ExportFilter {
+                collection,
+                tags: tag,
+                creators: creator,
+            };
+            let count = archive::export::export_archive(&state, &path, &filter).await?;
+            println!("exported {} media to {}", count, path.display());
+            return Ok(());
+        }
+        Some(Command::Import { archive: path }) => {
+            let summary = archive::import::import_archive(&state, &path).await?;
+            state
+                .sidecars
+                .sync(&state.storage_dir, &summary.media_ids, &state.conn)
+                .await?;
+            println!(
+                "imported {} new media, merged {} existing, with {} tags, {} creators and {} collections",
+                summary.media_added,
+                summary.media_merged,
+                summary.tags,
+                summary.creators,
+                summary.collections
+            );
+            return Ok(());
+        }
+        Some(Command::Sidecars) => {
+            if !state.sidecars.enabled() {
+                anyhow::bail!("SIDECARS is not set, eg. SIDECARS=json,xmp");
+            }
+            let count = sidecar::sync_all(&state).await?;
+            println!("wrote sidecars for {} media", count);
+            return Ok(());
+        }
+        Some(Command::FileSizes) => {
+            let count = backfill::file_sizes(&state).await?;
+            println!("recorded file sizes for {} media", count);
+            return Ok(());
+        }
+        Some(Command::TagImplications) => {
+            let changed = backfill::tag_implications(None, &state.conn).await?;
+            for chunk in changed.chunks(500) {
+                state
+                    .sidecars
+                    .sync(&state.storage_dir, chunk, &state.conn)
+                    .await?;
+            }
+            println!("added implied tags to {} media", changed.len());
+            return Ok(());
+        }
+        Some(Command::CanonicalSources) => {
+            let result = backfill::canonical_sources(&state).await?;
+            println!(
+                "rewrote {} sources and merged {} duplicates",
+                result.updated, result.merged
+            );
+            return Ok(());
+        }
+    }
+
     let (router, api) = OpenApiRouter::new()
         .routes(routes!(endpoints::media::post_media))
         // .routes(routes!(endpoints::media::update_media_item))
@@ -65,16 +186,38 @@ async fn main() -> anyhow::Result<()> {
         .routes(routes!(endpoints::media::get_media_file))
         .routes(routes!(endpoints::media::get_media_thumbnail))
         .routes(routes!(endpoints::media::get_media_item_by_hash))
+        .routes(routes!(endpoints::media::get_media_item_by_source))
         .routes(routes!(endpoints::media::delete_media_item))
         .routes(routes!(endpoints::media::get_media_item_creators))
         .routes(routes!(endpoints::media::get_media_item_collections))
         .routes(routes!(endpoints::media::get_media_item_tags))
+        .routes(routes!(endpoints::media::get_media_item_similar))
+        .routes(routes!(endpoints::bulk::media_bulk_edit))
 
         .routes(routes!(endpoints::search::search_query))
         .routes(routes!(endpoints::search::search_query_json))
         .routes(routes!(endpoints::search::hash_search))
+        .routes(routes!(endpoints::search::random_media))
+        .routes(routes!(endpoints::saved_searches::get_saved_searches))
+        .routes(routes!(endpoints::saved_searches::post_saved_search))
+        .routes(routes!(endpoints::saved_searches::get_saved_search))
+        .routes(routes!(endpoints::saved_searches::patch_saved_search))
+        .routes(routes!(endpoints::saved_searches::delete_saved_search))
+        .routes(routes!(endpoints::saved_searches::run_saved_search))
 
         .routes(routes!(endpoints::tags::search_tags))
+        .routes(routes!(endpoints::tags::get_tag_aliases))
+        .routes(routes!(endpoints::tags::post_tag_alias))
+        .routes(routes!(endpoints::tags::delete_tag_alias))
+        .routes(routes!(endpoints::tags::get_tag_implications))
+        .routes(routes!(endpoints::tags::post_tag_implication))
+        .routes(routes!(endpoints::tags::delete_tag_implication))
+        .routes(routes!(endpoints::tags::get_tags))
+        .routes(routes!(endpoints::tags::get_tag))
+        .routes(routes!(endpoints::tags::patch_tag))
+        .routes(routes!(endpoints::tags::merge_tags))
+        .routes(routes!(endpoints::tags::delete_tag))
+        .routes(routes!(endpoints::tags::delete_unused_tags))
         .routes(routes!(endpoints::autocomplete::autocomplete))
         .routes(routes!(endpoints::collection::get_collections))
         .routes(routes!(endpoints::collection::get_collection_id))
@@ -94,6 +237,7 @@ async fn main() -> anyhow::Result<()> {
 
 
 
+
         .split_for_parts();
 
     let app = router
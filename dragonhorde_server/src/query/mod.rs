@@ -0,0 +1,295 @@
+//! The search query language.
+//!
+//! Terms are tags, optionally qualified by a namespace, combined with boolean operators:
+//!
+//! ```text
+//! (dragon | wyvern) creator:foo -collection:wip rating:s species:*fox*
+//! ```
+//!
+//! * Terms next to each other must all match, `AND` and `&` may be written explicitly.
+//! * `|` or `OR` matches either side, and binds looser than AND.
+//! * `-`, `!` or `NOT` before a term or group negates it.
+//! * Parentheses group.
+//! * `*` in a value matches any run of characters. Values with spaces can be quoted,
+//!   eg. `title:"some title"`.
+//!
+//! Namespaces are `creator:` (or `artist:`), `collection:` (a path, eg. `comics/series`),
+//! `source:` (a url), `site:`, `title:`, `id:` and `tag:`. Any other namespace is a tag group, so
+//! `species:dragon` only matches the tag dragon in the species group.
+//!
+//! Image properties are matched with `type:png`, `transparent:true` and comparisons on `width`,
+//! `height`, `ratio` and `filesize`, eg. `width>=1920`, `ratio:16:9`, `ratio>1` or `filesize<5MB`.
+//! Sizes take the units B, KB, MB and GB, counted in 1024s.
+//!
+//! `created` and `uploaded` take a year, month, day or timestamp, eg. `created:2019` for anything
+//! made that year, or `uploaded>=2024-06-01`. A time ago finds anything since then, eg.
+//! `uploaded:7d`, in hours (h), days (d), weeks (w), months (m) or years (y).
+//!
+//! `text:` searches the words of titles, descriptions and source titles, eg. `text:bluefire` or
+//! `text:"red scales"` for a phrase.
+//!
+//! The tag, creator and collection lists of the older search forms are turned into the same
+//! expressions by [`from_lists`].
+
+mod parser;
+mod sql;
+
+pub use parser::{date, parse};
+use parser::term;
+pub use sql::{count, facets, random_ids, search_ids, search_page, smart_collections, snippets};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A value to match, where `*` stands for any run of characters
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern(pub String);
+
+impl Pattern {
+    pub fn has_wildcard(&self) -> bool {
+        self.0.contains('*')
+    }
+
+    /// The pattern for a LIKE comparison, with LIKE's own wildcards escaped
+    pub fn like(&self) -> String {
+        self.0
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+            .replace('*', "%")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term {
+    /// A tag by name, in a specific group if one is given
+    Tag {
+        group: Option<String>,
+        tag: Pattern,
+    },
+    /// A tag, or any tag that implies it
+    ImpliedTag {
+        group: Option<String>,
+        tag: Pattern,
+    },
+    /// A creator by any of their aliases
+    Creator(Pattern),
+    /// A collection by its path
+    Collection(Pattern),
+    /// A collection by its path, or any collection below it
+    CollectionTree(Pattern),
+    /// A source url
+    Source(Pattern),
+    /// The site of any source
+    Site(Pattern),
+    Title(Pattern),
+    Id(i64),
+    /// The file type, by extension
+    Type(Pattern),
+    Transparent(bool),
+    Width(Compare, i64),
+    Height(Compare, i64),
+    /// Width divided by height, where equal means within [`RATIO_TOLERANCE`]
+    Ratio(Compare, f64),
+    /// Size of the stored file in bytes
+    FileSize(Compare, i64),
+    /// When the work was made
+    Created(DateRange),
+    /// When the item was added to the library
+    Uploaded(DateRange),
+    /// Words of the title, description and source titles, in `websearch_to_tsquery` form
+    Text(String),
+}
+
+/// A span of time, from `after` up to but not including `before`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DateRange {
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+}
+
+/// How close a ratio has to be to count as equal, so 1366x768 is 16:9
+pub const RATIO_TOLERANCE: f64 = 0.01;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compare {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Compare {
+    pub fn sql(&self) -> &'static str {
+        match self {
+            Compare::Eq => " = ",
+            Compare::Lt => " < ",
+            Compare::Le => " <= ",
+            Compare::Gt => " > ",
+            Compare::Ge => " >= ",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+    Term(Term),
+}
+
+impl Expr {
+    /// The same expression with every collection term also matching sub-collections
+    pub fn with_sub_collections(self) -> Expr {
+        match self {
+            Expr::And(items) => {
+                Expr::And(items.into_iter().map(Expr::with_sub_collections).collect())
+            }
+            Expr::Or(items) => Expr::Or(items.into_iter().map(Expr::with_sub_collections).collect()),
+            Expr::Not(inner) => Expr::Not(Box::new(inner.with_sub_collections())),
+            Expr::Term(Term::Collection(path)) => Expr::Term(Term::CollectionTree(path)),
+            Expr::Term(term) => Expr::Term(term),
+        }
+    }
+
+    /// The same expression with every tag term also matching the tags that imply it
+    pub fn with_implied_tags(self) -> Expr {
+        match self {
+            Expr::And(items) => Expr::And(items.into_iter().map(Expr::with_implied_tags).collect()),
+            Expr::Or(items) => Expr::Or(items.into_iter().map(Expr::with_implied_tags).collect()),
+            Expr::Not(inner) => Expr::Not(Box::new(inner.with_implied_tags())),
+            Expr::Term(Term::Tag { group, tag }) => Expr::Term(Term::ImpliedTag { group, tag }),
+            Expr::Term(term) => Expr::Term(term),
+        }
+    }
+
+    /// The text searches an item is matched by, leaving out negated ones
+    pub fn text_queries(&self) -> Vec<&str> {
+        match self {
+            Expr::And(items) | Expr::Or(items) => {
+                items.iter().flat_map(|i| i.text_queries()).collect()
+            }
+            Expr::Not(_) => vec![],
+            Expr::Term(Term::Text(text)) => vec![text.as_str()],
+            Expr::Term(_) => vec![],
+        }
+    }
+}
+
+/// Why a query could not be parsed
+#[derive(utoipa::ToSchema, Clone, Debug, PartialEq, Serialize)]
+pub struct QueryError {
+    pub error: String,
+    /// Character offset in the query where the problem was found, or for list searches the
+    /// index of the entry
+    pub position: usize,
+}
+
+impl QueryError {
+    pub(crate) fn new(error: impl Into<String>, position: usize) -> Self {
+        Self {
+            error: error.into(),
+            position,
+        }
+    }
+}
+
+/// How the tags of a list search combine
+#[derive(utoipa::ToSchema, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagMatch {
+    /// Items must have every tag
+    #[default]
+    All,
+    /// Items must have at least one of the tags
+    Any,
+}
+
+/// Split a list entry into whether it is excluded, its namespace and its value
+fn list_entry(entry: &str) -> (bool, Option<&str>, &str) {
+    let (exclude, entry) = match entry.strip_prefix('-') {
+        Some(entry) => (true, entry),
+        None => (false, entry),
+    };
+    match entry.split_once(':') {
+        Some((namespace, value)) if !namespace.is_empty() => (exclude, Some(namespace), value),
+        _ => (exclude, None, entry),
+    }
+}
+
+/// Build an expression from lists of terms, where entries starting with - are excluded.
+///
+/// Tags combine according to `tag_match`, and may be qualified by their group, eg.
+/// `species:dragon`. An item matches the creator and collection lists if it has any of their
+/// included entries. A list that is missing entirely only matches items with none of that kind,
+/// while an empty list doesn't filter at all.
+pub fn from_lists(
+    tags: Option<&[String]>,
+    creators: Option<&[String]>,
+    collections: Option<&[String]>,
+    tag_match: TagMatch,
+) -> Result<Expr, QueryError> {
+    let mut all: Vec<Expr> = Vec::new();
+
+    match tags {
+        None => all.push(Expr::Not(Box::new(Expr::Term(term(
+            None,
+            "*".to_string(),
+            0,
+        )?)))),
+        Some(tags) => {
+            let mut include: Vec<Expr> = Vec::new();
+            for (i, entry) in tags.iter().enumerate() {
+                let (exclude, namespace, value) = list_entry(entry);
+                let expr = Expr::Term(term(namespace.map(String::from), value.to_string(), i)?);
+                if exclude {
+                    all.push(Expr::Not(Box::new(expr)));
+                } else {
+                    include.push(expr);
+                }
+            }
+            if !include.is_empty() {
+                all.push(match tag_match {
+                    TagMatch::All => Expr::And(include),
+                    TagMatch::Any => Expr::Or(include),
+                });
+            }
+        }
+    }
+
+    for (namespace, list) in [("creator", creators), ("collection", collections)] {
+        match list {
+            None => all.push(Expr::Not(Box::new(Expr::Term(term(
+                Some(namespace.to_string()),
+                "*".to_string(),
+                0,
+            )?)))),
+            Some(list) => {
+                let mut include: Vec<Expr> = Vec::new();
+                for (i, entry) in list.iter().enumerate() {
+                    let (exclude, value) = match entry.strip_prefix('-') {
+                        Some(value) => (true, value),
+                        None => (false, entry.as_str()),
+                    };
+                    let expr = Expr::Term(term(
+                        Some(namespace.to_string()),
+                        value.to_string(),
+                        i,
+                    )?);
+                    if exclude {
+                        all.push(Expr::Not(Box::new(expr)));
+                    } else {
+                        include.push(expr);
+                    }
+                }
+                if !include.is_empty() {
+                    all.push(Expr::Or(include));
+                }
+            }
+        }
+    }
+
+    Ok(Expr::And(all))
+}
@@ -0,0 +1,503 @@
+use crate::query::{Compare, DateRange, Expr, Pattern, QueryError, Term};
+use chrono::{DateTime, Days, Months, NaiveDate, TimeDelta, Utc};
+
+/// Namespaces that can be compared, which may be written without a colon, eg. `width>=1920`
+const COMPARABLE: [&str; 6] = ["width", "height", "ratio", "filesize", "created", "uploaded"];
+
+/// How deeply negations and parentheses may nest, so a query can't exhaust the stack
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Word {
+        namespace: Option<String>,
+        value: String,
+    },
+}
+
+fn lex(input: &str) -> Result<Vec<(usize, Token)>, QueryError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens: Vec<(usize, Token)> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '|' => Token::Or,
+            '&' => Token::And,
+            '-' | '!' => Token::Not,
+            _ => {
+                let start = i;
+                let mut namespace: Option<String> = None;
+                let mut value = String::new();
+                let mut quoted = false;
+                while i < chars.len() {
+                    let c = chars[i];
+                    if c == '"' {
+                        quoted = true;
+                        i += 1;
+                        loop {
+                            match chars.get(i) {
+                                None => return Err(QueryError::new("unterminated quote", start)),
+                                Some('\\') if i + 1 < chars.len() => {
+                                    value.push(chars[i + 1]);
+                                    i += 2;
+                                }
+                                Some('"') => {
+                                    i += 1;
+                                    break;
+                                }
+                                Some(c) => {
+                                    value.push(*c);
+                                    i += 1;
+                                }
+                            }
+                        }
+                        continue;
+                    }
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '|' {
+                        break;
+                    }
+                    // Only the first colon separates the namespace, eg. ratio:16:9
+                    if c == ':' && namespace.is_none() && !quoted && !value.is_empty() {
+                        namespace = Some(std::mem::take(&mut value));
+                    } else if matches!(c, '<' | '>' | '=')
+                        && namespace.is_none()
+                        && !quoted
+                        && COMPARABLE.contains(&value.to_lowercase().as_str())
+                    {
+                        // The comparison stays in the value for term to read
+                        namespace = Some(std::mem::take(&mut value));
+                        value.push(c);
+                    } else {
+                        value.push(c);
+                    }
+                    i += 1;
+                }
+                let token = match (&namespace, quoted, value.as_str()) {
+                    (None, false, "AND") => Token::And,
+                    (None, false, "OR") => Token::Or,
+                    (None, false, "NOT") => Token::Not,
+                    _ => Token::Word { namespace, value },
+                };
+                tokens.push((start, token));
+                continue;
+            }
+        };
+        tokens.push((i, token));
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+/// Split the comparison off the front of a value, eg. `>=1920`
+fn comparison(value: &str) -> (Compare, &str) {
+    for (prefix, compare) in [
+        (">=", Compare::Ge),
+        ("<=", Compare::Le),
+        (">", Compare::Gt),
+        ("<", Compare::Lt),
+        ("=", Compare::Eq),
+    ] {
+        if let Some(rest) = value.strip_prefix(prefix) {
+            return (compare, rest);
+        }
+    }
+    (Compare::Eq, value)
+}
+
+fn integer(value: &str, position: usize) -> Result<i64, QueryError> {
+    value
+        .parse()
+        .map_err(|_| QueryError::new(format!("{} is not a whole number", value), position))
+}
+
+/// A ratio written as `16:9` or as a number
+fn ratio(value: &str, position: usize) -> Result<f64, QueryError> {
+    let error = || QueryError::new(format!("{} is not a ratio, eg. 16:9", value), position);
+    let ratio = match value.split_once(':') {
+        Some((width, height)) => {
+            width.parse::<f64>().map_err(|_| error())?
+                / height.parse::<f64>().map_err(|_| error())?
+        }
+        None => value.parse::<f64>().map_err(|_| error())?,
+    };
+    if ratio.is_finite() && ratio > 0.0 {
+        Ok(ratio)
+    } else {
+        Err(error())
+    }
+}
+
+/// A size in bytes, with an optional unit, eg. `5MB`
+fn file_size(value: &str, position: usize) -> Result<i64, QueryError> {
+    let error = || QueryError::new(format!("{} is not a size, eg. 5MB", value), position);
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let multiplier: f64 = match unit.to_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "k" | "kb" | "kib" => 1024.0,
+        "m" | "mb" | "mib" => 1024.0 * 1024.0,
+        "g" | "gb" | "gib" => 1024.0 * 1024.0 * 1024.0,
+        _ => return Err(error()),
+    };
+    let number: f64 = number.parse().map_err(|_| error())?;
+    Ok((number * multiplier).round() as i64)
+}
+
+/// A time ago, eg. `7d`
+fn ago(value: &str) -> Option<DateTime<Utc>> {
+    let split = value.find(|c: char| !c.is_ascii_digit())?;
+    let (number, unit) = value.split_at(split);
+    let number: u32 = number.parse().ok()?;
+    let now = Utc::now();
+    match unit {
+        "h" => now.checked_sub_signed(TimeDelta::try_hours(number.into())?),
+        "d" => now.checked_sub_days(Days::new(number.into())),
+        "w" => now.checked_sub_days(Days::new(u64::from(number) * 7)),
+        "m" => now.checked_sub_months(Months::new(number)),
+        "y" => now.checked_sub_months(Months::new(number.checked_mul(12)?)),
+        _ => None,
+    }
+}
+
+/// The span of a year, month, day or timestamp, eg. `2019`, `2019-05`, `2019-05-01`
+fn period(value: &str) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let parts: Vec<&str> = value.split('-').collect();
+    let (start, end) = match parts[..] {
+        [year] if year.len() == 4 => {
+            let start = NaiveDate::from_ymd_opt(year.parse().ok()?, 1, 1)?;
+            (start, start.checked_add_months(Months::new(12))?)
+        }
+        [year, month] => {
+            let start = NaiveDate::from_ymd_opt(year.parse().ok()?, month.parse().ok()?, 1)?;
+            (start, start.checked_add_months(Months::new(1))?)
+        }
+        [_, _, _] => {
+            let start = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+            (start, start.checked_add_days(Days::new(1))?)
+        }
+        _ => {
+            let instant = DateTime::parse_from_rfc3339(value).ok()?.to_utc();
+            return Some((instant, instant + TimeDelta::seconds(1)));
+        }
+    };
+    Some((
+        start.and_hms_opt(0, 0, 0)?.and_utc(),
+        end.and_hms_opt(0, 0, 0)?.and_utc(),
+    ))
+}
+
+fn date_range(value: &str, position: usize) -> Result<DateRange, QueryError> {
+    let (compare, value) = comparison(value);
+    if let Some(since) = ago(value) {
+        return match compare {
+            Compare::Eq => Ok(DateRange {
+                after: Some(since),
+                before: None,
+            }),
+            _ => Err(QueryError::new(
+                format!("{} is a time ago, which can't be compared, use a date instead", value),
+                position,
+            )),
+        };
+    }
+    let (start, end) = period(value).ok_or_else(|| {
+        QueryError::new(
+            format!("{} is not a date, eg. 2019-05-01, or a time ago, eg. 7d", value),
+            position,
+        )
+    })?;
+    let (after, before) = match compare {
+        Compare::Eq => (Some(start), Some(end)),
+        Compare::Lt => (None, Some(start)),
+        Compare::Le => (None, Some(end)),
+        Compare::Gt => (Some(end), None),
+        Compare::Ge => (Some(start), None),
+    };
+    Ok(DateRange { after, before })
+}
+
+/// The start of a date, or the time a while ago, as taken by the date fields of a JSON search
+pub fn date(value: &str) -> Result<DateTime<Utc>, QueryError> {
+    ago(value)
+        .or_else(|| period(value).map(|(start, _)| start))
+        .ok_or_else(|| {
+            QueryError::new(
+                format!("{} is not a date, eg. 2019-05-01, or a time ago, eg. 7d", value),
+                0,
+            )
+        })
+}
+
+pub(super) fn term(
+    namespace: Option<String>,
+    value: String,
+    position: usize,
+) -> Result<Term, QueryError> {
+    if value.is_empty() {
+        return Err(QueryError::new(
+            format!("{}: needs a value", namespace.unwrap_or_default()),
+            position,
+        ));
+    }
+    let namespace = namespace.map(|n| n.to_lowercase());
+    Ok(match namespace.as_deref() {
+        None | Some("tag") => Term::Tag {
+            group: None,
+            tag: Pattern(value.to_lowercase()),
+        },
+        Some("creator") | Some("artist") => Term::Creator(Pattern(value.to_lowercase())),
+        Some("collection") => Term::Collection(Pattern(value)),
+        Some("source") => Term::Source(Pattern(value)),
+        Some("site") => Term::Site(Pattern(value.to_lowercase())),
+        Some("title") => Term::Title(Pattern(value)),
+        Some("id") => Term::Id(
+            value
+                .parse()
+                .map_err(|_| QueryError::new(format!("{} is not a media id", value), position))?,
+        ),
+        Some("type") => Term::Type(Pattern(match value.to_lowercase().as_str() {
+            "jpeg" => "jpg".to_string(),
+            other => other.to_string(),
+        })),
+        Some("transparent") => Term::Transparent(match value.to_lowercase().as_str() {
+            "true" | "yes" => true,
+            "false" | "no" => false,
+            _ => {
+                return Err(QueryError::new(
+                    format!("transparent: needs true or false, not {}", value),
+                    position,
+                ))
+            }
+        }),
+        Some("width") => {
+            let (compare, value) = comparison(&value);
+            Term::Width(compare, integer(value, position)?)
+        }
+        Some("height") => {
+            let (compare, value) = comparison(&value);
+            Term::Height(compare, integer(value, position)?)
+        }
+        Some("ratio") => {
+            let (compare, value) = comparison(&value);
+            Term::Ratio(compare, ratio(value, position)?)
+        }
+        Some("filesize") => {
+            let (compare, value) = comparison(&value);
+            Term::FileSize(compare, file_size(value, position)?)
+        }
+        Some("created") => Term::Created(date_range(&value, position)?),
+        Some("uploaded") => Term::Uploaded(date_range(&value, position)?),
+        // More than one word is a phrase
+        Some("text") if value.contains(char::is_whitespace) => {
+            Term::Text(format!("\"{}\"", value.replace('"', "")))
+        }
+        Some("text") => Term::Text(value),
+        Some(group) => Term::Tag {
+            group: Some(group.to_string()),
+            tag: Pattern(value.to_lowercase()),
+        },
+    })
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map(|(p, _)| *p).unwrap_or(self.end)
+    }
+
+    fn next(&mut self) -> Option<(usize, Token)> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// Go a level deeper into a negation or group
+    fn descend(&mut self, position: usize) -> Result<(), QueryError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(QueryError::new(
+                format!("the query nests more than {} levels deep", MAX_DEPTH),
+                position,
+            ));
+        }
+        Ok(())
+    }
+
+    fn or(&mut self) -> Result<Expr, QueryError> {
+        let mut items = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            items.push(self.and()?);
+        }
+        Ok(if items.len() == 1 {
+            items.remove(0)
+        } else {
+            Expr::Or(items)
+        })
+    }
+
+    fn and(&mut self) -> Result<Expr, QueryError> {
+        let mut items = vec![self.unary()?];
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.next();
+                    items.push(self.unary()?);
+                }
+                Some(Token::Word { .. }) | Some(Token::LParen) | Some(Token::Not) => {
+                    items.push(self.unary()?);
+                }
+                _ => break,
+            }
+        }
+        Ok(if items.len() == 1 {
+            items.remove(0)
+        } else {
+            Expr::And(items)
+        })
+    }
+
+    fn unary(&mut self) -> Result<Expr, QueryError> {
+        if self.peek() == Some(&Token::Not) {
+            self.descend(self.position())?;
+            self.next();
+            let expr = Expr::Not(Box::new(self.unary()?));
+            self.depth -= 1;
+            return Ok(expr);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, QueryError> {
+        let position = self.position();
+        match self.next() {
+            Some((_, Token::LParen)) => {
+                self.descend(position)?;
+                let expr = self.or()?;
+                self.depth -= 1;
+                match self.next() {
+                    Some((_, Token::RParen)) => Ok(expr),
+                    _ => Err(QueryError::new("missing closing parenthesis", position)),
+                }
+            }
+            Some((_, Token::Word { namespace, value })) => {
+                Ok(Expr::Term(term(namespace, value, position)?))
+            }
+            Some((_, Token::RParen)) => Err(QueryError::new("unexpected )", position)),
+            Some((_, Token::And)) | Some((_, Token::Or)) => {
+                Err(QueryError::new("expected a term before the operator", position))
+            }
+            Some((_, Token::Not)) => unreachable!("NOT is handled by unary"),
+            None => Err(QueryError::new("expected a term at the end of the query", position)),
+        }
+    }
+}
+
+/// Parse a query string. An empty query matches everything
+pub fn parse(input: &str) -> Result<Expr, QueryError> {
+    let tokens = lex(input)?;
+    if tokens.is_empty() {
+        return Ok(Expr::And(vec![]));
+    }
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end: input.chars().count(),
+        depth: 0,
+    };
+    let expr = parser.or()?;
+    if parser.peek().is_some() {
+        return Err(QueryError::new("unexpected )", parser.position()));
+    }
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(tag: &str) -> Expr {
+        Expr::Term(Term::Tag {
+            group: None,
+            tag: Pattern(tag.to_string()),
+        })
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse("a b | c").unwrap(),
+            Expr::Or(vec![Expr::And(vec![tag("a"), tag("b")]), tag("c")])
+        );
+        assert_eq!(
+            parse("a | b & c").unwrap(),
+            Expr::Or(vec![tag("a"), Expr::And(vec![tag("b"), tag("c")])])
+        );
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        assert_eq!(
+            parse("-a b").unwrap(),
+            Expr::And(vec![Expr::Not(Box::new(tag("a"))), tag("b")])
+        );
+        assert_eq!(
+            parse("NOT (a | b)").unwrap(),
+            Expr::Not(Box::new(Expr::Or(vec![tag("a"), tag("b")])))
+        );
+    }
+
+    #[test]
+    fn parentheses_group() {
+        assert_eq!(
+            parse("(a | b) c").unwrap(),
+            Expr::And(vec![Expr::Or(vec![tag("a"), tag("b")]), tag("c")])
+        );
+    }
+
+    #[test]
+    fn nesting_up_to_the_limit_parses() {
+        let query = format!("{}a", "-".repeat(MAX_DEPTH));
+        assert!(parse(&query).is_ok());
+        let query = format!("{}a{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert_eq!(parse(&query).unwrap(), tag("a"));
+    }
+
+    #[test]
+    fn nesting_past_the_limit_is_an_error() {
+        assert!(parse(&format!("{}a", "-".repeat(MAX_DEPTH + 1))).is_err());
+        assert!(parse(&format!("{}a", "-".repeat(20_000))).is_err());
+        let query = format!("{}a{}", "(".repeat(20_000), ")".repeat(20_000));
+        assert!(parse(&query).is_err());
+    }
+
+    #[test]
+    fn siblings_do_not_count_towards_the_depth() {
+        let query = vec!["(-a)"; MAX_DEPTH * 2].join(" ");
+        assert!(parse(&query).is_ok());
+    }
+}
@@ -0,0 +1,556 @@
+use crate::api_models::{Cursor, FacetCount, Facets, Sort, SortBy, SortOrder, TotalCount};
+use crate::query::{Compare, DateRange, Expr, Pattern, Term, RATIO_TOLERANCE};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use std::collections::HashMap;
+
+/// Every collection with its full path, for matching `collection:` terms. Postgres only evaluates
+/// it when a term refers to it
+const COLLECTION_PATHS: &str = r#"
+WITH RECURSIVE collection_paths AS (SELECT id, name::text AS path
+                                    FROM collections
+                                    WHERE parent IS NULL
+                                    UNION ALL
+                                    SELECT c.id, cp.path || '/' || c.name
+                                    FROM collection_paths cp
+                                             JOIN collections c ON c.parent = cp.id)
+"#;
+
+/// Width over height, in the same form as the index on it
+const RATIO: &str = "(media.width::double precision / NULLIF(media.height, 0))";
+
+/// Compare a column to a pattern. Stored tags, aliases and sites are already lower case, so only
+/// free text needs the case insensitive forms
+fn push_match(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    column: &str,
+    pattern: &Pattern,
+    case_insensitive: bool,
+) {
+    if pattern.has_wildcard() {
+        builder
+            .push(column)
+            .push(if case_insensitive { " ILIKE " } else { " LIKE " })
+            .push_bind(pattern.like())
+            .push(" ESCAPE '\\'");
+    } else if case_insensitive {
+        builder
+            .push("LOWER(")
+            .push(column)
+            .push(") = LOWER(")
+            .push_bind(pattern.0.clone())
+            .push(")");
+    } else {
+        builder.push(column).push(" = ").push_bind(pattern.0.clone());
+    }
+}
+
+/// Match collection paths to a pattern, or lying below a path that does
+fn push_subtree(builder: &mut QueryBuilder<'_, Postgres>, path: &Pattern) {
+    builder.push("(");
+    push_match(builder, "collection_paths.path", path, true);
+    builder
+        .push(" OR collection_paths.path ILIKE ")
+        .push_bind(format!("{}/%", path.like()))
+        .push(" ESCAPE '\\')");
+}
+
+/// Text search configuration, matching the one `media.search_text` is built with
+const TEXT_CONFIG: &str = "'english'";
+
+/// Any of the text searches, as a tsquery
+fn push_tsquery(builder: &mut QueryBuilder<'_, Postgres>, texts: &[&str]) {
+    if texts.is_empty() {
+        builder.push("''::tsquery");
+        return;
+    }
+    builder.push("(");
+    for (i, text) in texts.iter().enumerate() {
+        if i > 0 {
+            builder.push(" || ");
+        }
+        builder
+            .push("WEBSEARCH_TO_TSQUERY(")
+            .push(TEXT_CONFIG)
+            .push(", ")
+            .push_bind(text.to_string())
+            .push(")");
+    }
+    builder.push(")");
+}
+
+fn push_date_range(builder: &mut QueryBuilder<'_, Postgres>, column: &str, range: &DateRange) {
+    builder.push("(").push(column).push(" IS NOT NULL");
+    if let Some(after) = range.after {
+        builder.push(" AND ").push(column).push(" >= ").push_bind(after);
+    }
+    if let Some(before) = range.before {
+        builder.push(" AND ").push(column).push(" < ").push_bind(before);
+    }
+    builder.push(")");
+}
+
+/// Ids of the tags a tag term names, by name or by alias, in its group if it has one
+fn push_tag_ids(builder: &mut QueryBuilder<'_, Postgres>, group: &Option<String>, tag: &Pattern) {
+    builder.push("SELECT tags.id FROM tags ");
+    if group.is_some() {
+        builder.push(r#"JOIN tag_groups ON tag_groups.id = tags."group" "#);
+    }
+    builder.push("WHERE (");
+    push_match(builder, "tags.tag", tag, false);
+    builder.push(" OR EXISTS (SELECT 1 FROM tag_alias WHERE tag_alias.tag = tags.id AND ");
+    push_match(builder, "tag_alias.alias", tag, false);
+    builder.push("))");
+    if let Some(group) = group {
+        builder
+            .push(" AND tag_groups.name = ")
+            .push_bind(group.clone());
+    }
+}
+
+fn push_term(builder: &mut QueryBuilder<'_, Postgres>, term: &Term) {
+    match term {
+        Term::Tag { group, tag } => {
+            builder.push(
+                "EXISTS (SELECT 1 FROM media_tags WHERE media_tags.media_id = media.id AND media_tags.tag_id IN (",
+            );
+            push_tag_ids(builder, group, tag);
+            builder.push("))");
+        }
+        Term::ImpliedTag { group, tag } => {
+            builder.push(
+                "EXISTS (SELECT 1 FROM media_tags WHERE media_tags.media_id = media.id AND media_tags.tag_id IN (WITH RECURSIVE implying AS (",
+            );
+            push_tag_ids(builder, group, tag);
+            builder.push(
+                " UNION SELECT tag_implications.tag FROM tag_implications JOIN implying ON tag_implications.implies = implying.id) SELECT id FROM implying))",
+            );
+        }
+        Term::Creator(creator) => {
+            builder.push(
+                r#"EXISTS (SELECT 1 FROM media_creators
+                   JOIN creator_alias ON creator_alias.creator = media_creators.creator_id
+                   WHERE media_creators.media_id = media.id AND "#,
+            );
+            push_match(builder, "creator_alias.alias", creator, false);
+            builder.push(")");
+        }
+        Term::Collection(path) => {
+            builder.push(
+                r#"EXISTS (SELECT 1 FROM media_collection
+                   JOIN collection_paths ON collection_paths.id = media_collection.collection_id
+                   WHERE media_collection.media_id = media.id AND "#,
+            );
+            push_match(builder, "collection_paths.path", path, true);
+            builder.push(")");
+        }
+        Term::CollectionTree(path) => {
+            builder.push(
+                r#"EXISTS (SELECT 1 FROM media_collection
+                   JOIN collection_paths ON collection_paths.id = media_collection.collection_id
+                   WHERE media_collection.media_id = media.id AND "#,
+            );
+            push_subtree(builder, path);
+            builder.push(")");
+        }
+        Term::Source(url) => {
+            builder.push("EXISTS (SELECT 1 FROM sources WHERE sources.media_id = media.id AND ");
+            push_match(builder, "sources.source", url, true);
+            builder.push(")");
+        }
+        Term::Site(site) => {
+            builder.push("EXISTS (SELECT 1 FROM sources WHERE sources.media_id = media.id AND ");
+            push_match(builder, "sources.site", site, false);
+            builder.push(")");
+        }
+        Term::Title(title) => push_match(builder, "media.title", title, true),
+        Term::Id(id) => {
+            builder.push("media.id = ").push_bind(*id);
+        }
+        Term::Type(file_type) => push_match(builder, "media.type", file_type, false),
+        Term::Transparent(transparent) => {
+            builder.push("media.transparent = ").push_bind(*transparent);
+        }
+        Term::Width(compare, width) => {
+            builder.push("media.width").push(compare.sql()).push_bind(*width);
+        }
+        Term::Height(compare, height) => {
+            builder.push("media.height").push(compare.sql()).push_bind(*height);
+        }
+        Term::Ratio(Compare::Eq, ratio) => {
+            builder
+                .push(RATIO)
+                .push(" BETWEEN ")
+                .push_bind(ratio - RATIO_TOLERANCE)
+                .push(" AND ")
+                .push_bind(ratio + RATIO_TOLERANCE);
+        }
+        Term::Ratio(compare, ratio) => {
+            builder.push(RATIO).push(compare.sql()).push_bind(*ratio);
+        }
+        Term::FileSize(compare, size) => {
+            builder.push("media.file_size").push(compare.sql()).push_bind(*size);
+        }
+        Term::Created(range) => push_date_range(builder, "media.created", range),
+        Term::Uploaded(range) => push_date_range(builder, "media.uploaded", range),
+        Term::Text(text) => {
+            builder.push("media.search_text @@ ");
+            push_tsquery(builder, &[text]);
+        }
+    }
+}
+
+/// Add the SQL condition for an expression, in terms of a `media` row
+pub fn push_condition(builder: &mut QueryBuilder<'_, Postgres>, expr: &Expr) {
+    match expr {
+        Expr::And(items) if items.is_empty() => {
+            builder.push("TRUE");
+        }
+        Expr::Or(items) if items.is_empty() => {
+            builder.push("FALSE");
+        }
+        Expr::And(items) | Expr::Or(items) => {
+            let op = if matches!(expr, Expr::And(_)) {
+                " AND "
+            } else {
+                " OR "
+            };
+            builder.push("(");
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    builder.push(op);
+                }
+                push_condition(builder, item);
+            }
+            builder.push(")");
+        }
+        Expr::Not(expr) => {
+            builder.push("NOT (");
+            push_condition(builder, expr);
+            builder.push(")");
+        }
+        Expr::Term(term) => push_term(builder, term),
+    }
+}
+
+/// The value a sort orders by, and the type its text form in a cursor is read back as
+fn push_sort_key(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    by: SortBy,
+    sort: &Sort,
+    expr: &Expr,
+) -> &'static str {
+    match by {
+        SortBy::Uploaded => {
+            builder.push("media.uploaded");
+            "timestamptz"
+        }
+        SortBy::Created => {
+            builder.push("media.created");
+            "timestamptz"
+        }
+        SortBy::Title => {
+            builder.push("LOWER(media.title)");
+            "text"
+        }
+        SortBy::Resolution => {
+            builder.push("(media.width::bigint * media.height)");
+            "bigint"
+        }
+        SortBy::FileSize => {
+            builder.push("media.file_size");
+            "bigint"
+        }
+        SortBy::TagCount => {
+            builder.push("(SELECT COUNT(*) FROM media_tags WHERE media_tags.media_id = media.id)");
+            "bigint"
+        }
+        SortBy::Random => {
+            builder
+                .push("MD5(media.id::text || ':' || ")
+                .push_bind(sort.seed.unwrap_or_default())
+                .push("::text)");
+            "text"
+        }
+        SortBy::Similarity => {
+            builder
+                .push("(media.perceptual_hash <~> (SELECT target.perceptual_hash FROM media AS target WHERE target.id = ")
+                .push_bind(sort.similar_to)
+                .push("))");
+            "double precision"
+        }
+        SortBy::Relevance => {
+            builder.push("TS_RANK_CD(media.search_text, ");
+            push_tsquery(builder, &expr.text_queries());
+            builder.push(")");
+            "real"
+        }
+    }
+}
+
+/// Add the ORDER BY clause for a sort. Items without a value for the sort come last either way,
+/// and ties are broken by id so pages don't overlap
+pub fn push_order(builder: &mut QueryBuilder<'_, Postgres>, sort: &Sort, expr: &Expr) {
+    let by = sort.by(expr);
+    let direction = match sort.order(by) {
+        SortOrder::Asc => " ASC",
+        SortOrder::Desc => " DESC",
+    };
+    builder.push(" ORDER BY ");
+    push_sort_key(builder, by, sort, expr);
+    builder
+        .push(direction)
+        .push(" NULLS LAST, media.id")
+        .push(direction);
+}
+
+/// Add the condition for items after a cursor in the order of [`push_order`]
+fn push_after(builder: &mut QueryBuilder<'_, Postgres>, sort: &Sort, expr: &Expr, cursor: &Cursor) {
+    let by = sort.by(expr);
+    let after = match sort.order(by) {
+        SortOrder::Asc => " > ",
+        SortOrder::Desc => " < ",
+    };
+    match &cursor.key {
+        Some(key) => {
+            builder.push(" AND (");
+            let key_type = push_sort_key(builder, by, sort, expr);
+            builder
+                .push(after)
+                .push_bind(key.clone())
+                .push("::")
+                .push(key_type)
+                .push(" OR (");
+            push_sort_key(builder, by, sort, expr);
+            builder
+                .push(" = ")
+                .push_bind(key.clone())
+                .push("::")
+                .push(key_type)
+                .push(" AND media.id")
+                .push(after)
+                .push_bind(cursor.id)
+                .push(") OR ");
+            push_sort_key(builder, by, sort, expr);
+            builder.push(" IS NULL)");
+        }
+        // Only items without a value are left
+        None => {
+            builder.push(" AND ");
+            push_sort_key(builder, by, sort, expr);
+            builder
+                .push(" IS NULL AND media.id")
+                .push(after)
+                .push_bind(cursor.id);
+        }
+    }
+}
+
+/// A page of the media matching an expression, starting after a cursor, or at an offset without
+/// one. Returns the ids and the cursor for the next page, if there is one
+pub async fn search_page(
+    expr: &Expr,
+    sort: &Sort,
+    limit: i64,
+    offset: i64,
+    cursor: Option<&Cursor>,
+    db: &PgPool,
+) -> Result<(Vec<i64>, Option<String>), sqlx::Error> {
+    let by = sort.by(expr);
+    let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(COLLECTION_PATHS);
+    builder.push("SELECT media.id, ");
+    push_sort_key(&mut builder, by, sort, expr);
+    builder.push("::text FROM media WHERE ");
+    push_condition(&mut builder, expr);
+    if let Some(cursor) = cursor {
+        push_after(&mut builder, sort, expr, cursor);
+    }
+    push_order(&mut builder, sort, expr);
+    // One more than the page shows whether there is another page
+    builder
+        .push(" LIMIT ")
+        .push_bind(limit.saturating_add(1))
+        .push(" OFFSET ")
+        .push_bind(offset);
+    let rows: Vec<(i64, Option<String>)> = builder.build_query_as().fetch_all(db).await?;
+    Ok(Cursor::page(rows, limit, &sort.label(expr)))
+}
+
+/// Up to `count` random media matching an expression, leaving out `exclude`. Each pick jumps to a
+/// random point in the id range and takes the first match from there, wrapping around, so it walks
+/// the primary key rather than sorting every match. Gaps in the ids make the choice only roughly
+/// uniform, and picks may repeat.
+pub async fn random_ids(
+    expr: &Expr,
+    count: i64,
+    exclude: &[i64],
+    db: &PgPool,
+) -> Result<Vec<i64>, sqlx::Error> {
+    let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(COLLECTION_PATHS);
+    builder
+        .push(
+            r#", bounds AS (SELECT MIN(id) AS low, MAX(id) AS high FROM media),
+            picks AS (SELECT low + FLOOR(RANDOM() * (high - low + 1))::bigint AS start
+                      FROM bounds, GENERATE_SERIES(1, "#,
+        )
+        .push_bind(count)
+        .push(
+            r#"))
+            SELECT COALESCE((SELECT media.id FROM media WHERE media.id >= picks.start AND "#,
+        );
+    push_condition(&mut builder, expr);
+    builder
+        .push(" AND media.id <> ALL(")
+        .push_bind(exclude.to_vec())
+        .push(") ORDER BY media.id LIMIT 1), (SELECT media.id FROM media WHERE ");
+    push_condition(&mut builder, expr);
+    builder
+        .push(" AND media.id <> ALL(")
+        .push_bind(exclude.to_vec())
+        .push(") ORDER BY media.id LIMIT 1)) FROM picks");
+    let picks: Vec<Option<i64>> = builder.build_query_scalar().fetch_all(db).await?;
+    Ok(picks.into_iter().flatten().collect())
+}
+
+/// Ids of the media matching an expression, in the given order
+pub async fn search_ids(
+    expr: &Expr,
+    sort: &Sort,
+    limit: i64,
+    offset: i64,
+    db: &PgPool,
+) -> Result<Vec<i64>, sqlx::Error> {
+    Ok(search_page(expr, sort, limit, offset, None, db).await?.0)
+}
+
+/// The smart collections a `collection:` pattern names, or that lie below them as well when
+/// `sub_collections` is set, with the saved search behind each
+pub async fn smart_collections(
+    pattern: &Pattern,
+    sub_collections: bool,
+    db: impl sqlx::PgExecutor<'_>,
+) -> Result<Vec<(i64, i64)>, sqlx::Error> {
+    let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(COLLECTION_PATHS);
+    builder.push(
+        "SELECT collections.id, collections.saved_search FROM collection_paths JOIN collections ON collections.id = collection_paths.id WHERE collections.saved_search IS NOT NULL AND ",
+    );
+    if sub_collections {
+        push_subtree(&mut builder, pattern);
+    } else {
+        push_match(&mut builder, "collection_paths.path", pattern, true);
+    }
+    builder.build_query_as().fetch_all(db).await
+}
+
+/// How many media match an expression, counted or as the planner estimates
+pub async fn count(expr: &Expr, total: TotalCount, db: &PgPool) -> Result<i64, sqlx::Error> {
+    let mut builder: QueryBuilder<Postgres> = QueryBuilder::new("");
+    match total {
+        TotalCount::Exact => {
+            builder.push(COLLECTION_PATHS);
+            builder.push("SELECT COUNT(*) FROM media WHERE ");
+            push_condition(&mut builder, expr);
+            builder.build_query_scalar().fetch_one(db).await
+        }
+        TotalCount::Estimate => {
+            builder.push("EXPLAIN (FORMAT JSON) ");
+            builder.push(COLLECTION_PATHS);
+            builder.push("SELECT media.id FROM media WHERE ");
+            push_condition(&mut builder, expr);
+            let plan: sqlx::types::Json<serde_json::Value> =
+                builder.build_query_scalar().fetch_one(db).await?;
+            Ok(plan.0[0]["Plan"]["Plan Rows"].as_f64().unwrap_or_default() as i64)
+        }
+    }
+}
+
+/// Highlighted passages of the title and description of each item showing where the expression's
+/// text searches matched, with matches wrapped in `<mark>`. Empty if it has no text searches
+pub async fn snippets(
+    expr: &Expr,
+    ids: &[i64],
+    db: &PgPool,
+) -> Result<HashMap<i64, String>, sqlx::Error> {
+    let texts = expr.text_queries();
+    if texts.is_empty() || ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let mut builder: QueryBuilder<Postgres> = QueryBuilder::new("SELECT media.id, TS_HEADLINE(");
+    builder
+        .push(TEXT_CONFIG)
+        .push(", CONCAT_WS(E'\\n', media.title, media.description), ");
+    push_tsquery(&mut builder, &texts);
+    builder
+        .push(
+            ", 'StartSel=<mark>, StopSel=</mark>, MaxFragments=2, FragmentDelimiter=\" … \"') FROM media WHERE media.id = ANY(",
+        )
+        .push_bind(ids.to_vec())
+        .push(") AND media.search_text @@ ");
+    push_tsquery(&mut builder, &texts);
+    builder
+        .build_query_as::<(i64, String)>()
+        .fetch_all(db)
+        .await
+        .map(|rows| rows.into_iter().collect())
+}
+
+/// The `limit` most common tags, creators and collections among all media matching an expression
+pub async fn facets(expr: &Expr, limit: i64, db: &PgPool) -> Result<Facets, sqlx::Error> {
+    let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(COLLECTION_PATHS);
+    builder.push(", matched AS (SELECT media.id FROM media WHERE ");
+    push_condition(&mut builder, expr);
+    builder
+        .push(
+            r#")
+            (SELECT 'tag', tag_groups.name, tags.tag, COUNT(*)
+             FROM matched
+                      JOIN media_tags ON media_tags.media_id = matched.id
+                      JOIN tags ON tags.id = media_tags.tag_id
+                      LEFT JOIN tag_groups ON tag_groups.id = tags."group"
+             GROUP BY tag_groups.name, tags.tag
+             ORDER BY COUNT(*) DESC, tags.tag
+             LIMIT "#,
+        )
+        .push_bind(limit)
+        .push(
+            r#")
+            UNION ALL
+            (SELECT 'creator', NULL, creators.name, COUNT(*)
+             FROM matched
+                      JOIN media_creators ON media_creators.media_id = matched.id
+                      JOIN creators ON creators.id = media_creators.creator_id
+             GROUP BY creators.name
+             ORDER BY COUNT(*) DESC, creators.name
+             LIMIT "#,
+        )
+        .push_bind(limit)
+        .push(
+            r#")
+            UNION ALL
+            (SELECT 'collection', NULL, collection_paths.path, COUNT(*)
+             FROM matched
+                      JOIN media_collection ON media_collection.media_id = matched.id
+                      JOIN collection_paths ON collection_paths.id = media_collection.collection_id
+             GROUP BY collection_paths.path
+             ORDER BY COUNT(*) DESC, collection_paths.path
+             LIMIT "#,
+        )
+        .push_bind(limit)
+        .push(")");
+
+    let rows: Vec<(String, Option<String>, String, i64)> =
+        builder.build_query_as().fetch_all(db).await?;
+    let mut facets = Facets::default();
+    for (kind, group, value, count) in rows {
+        let facet = FacetCount { value, count };
+        match kind.as_str() {
+            "tag" => facets
+                .tags
+                .entry(group.unwrap_or_default())
+                .or_default()
+                .push(facet),
+            "creator" => facets.creators.push(facet),
+            _ => facets.collections.push(facet),
+        }
+    }
+    Ok(facets)
+}
@@ -0,0 +1,228 @@
+//! Metadata files kept next to each original in the storage directory.
+//!
+//! A JSON sidecar holds everything needed to rebuild the item's record, an XMP sidecar carries the
+//! parts other tools understand: title, description, creators and tags as keywords. Sidecars are
+//! named after the stored file, eg. `<sha256>.png.json` and `<sha256>.png.xmp`.
+
+use crate::api_models::{ApiMediaReturn, ApiSource};
+use crate::AppState;
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use serde_with::skip_serializing_none;
+use sqlx::types::BitVec;
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Which sidecars to write, set with the SIDECARS variable, eg. `json,xmp`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SidecarFormats {
+    pub json: bool,
+    pub xmp: bool,
+}
+
+impl FromStr for SidecarFormats {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut formats = SidecarFormats::default();
+        for format in s.split(',').map(|f| f.trim().to_lowercase()) {
+            match format.as_str() {
+                "" | "none" => {}
+                "json" => formats.json = true,
+                "xmp" => formats.xmp = true,
+                _ => return Err(format!("unknown sidecar format {}", format)),
+            }
+        }
+        Ok(formats)
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Serialize)]
+struct JsonSidecar<'a> {
+    sha256: &'a str,
+    perceptual_hash: Option<i64>,
+    file_type: Option<&'a str>,
+    uploaded: DateTime<FixedOffset>,
+    created: Option<DateTime<FixedOffset>>,
+    title: Option<&'a str>,
+    description: Option<&'a str>,
+    creators: Vec<String>,
+    tag_groups: BTreeMap<String, Vec<String>>,
+    sources: Vec<ApiSource>,
+    collections: Vec<String>,
+    metadata: Option<&'a serde_json::Value>,
+}
+
+fn sidecar_path(storage_dir: &Path, storage_uri: &str, extension: &str) -> PathBuf {
+    storage_dir.join(format!("{}.{}", storage_uri, extension))
+}
+
+/// Write through a temporary file, so a crash never leaves half a sidecar behind
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn xmp_list(out: &mut String, property: &str, container: &str, values: &[String]) {
+    if values.is_empty() {
+        return;
+    }
+    out.push_str(&format!("   <{}>\n    <rdf:{}>\n", property, container));
+    for value in values {
+        out.push_str(&format!("     <rdf:li>{}</rdf:li>\n", xml_escape(value)));
+    }
+    out.push_str(&format!("    </rdf:{}>\n   </{}>\n", container, property));
+}
+
+fn xmp_alt(out: &mut String, property: &str, value: Option<&str>) {
+    if let Some(value) = value {
+        out.push_str(&format!(
+            "   <{0}>\n    <rdf:Alt>\n     <rdf:li xml:lang=\"x-default\">{1}</rdf:li>\n    </rdf:Alt>\n   </{0}>\n",
+            property,
+            xml_escape(value)
+        ));
+    }
+}
+
+fn xmp_document(sidecar: &JsonSidecar) -> String {
+    let tags: Vec<String> = sidecar.tag_groups.values().flatten().cloned().collect();
+    // Hierarchical keywords keep the tag group, and put collections under their own root
+    let hierarchical: Vec<String> = sidecar
+        .tag_groups
+        .iter()
+        .flat_map(|(group, tags)| tags.iter().map(move |t| format!("{}|{}", group, t)))
+        .chain(
+            sidecar
+                .collections
+                .iter()
+                .map(|c| format!("collection|{}", c.replace('/', "|"))),
+        )
+        .collect();
+    let sources: Vec<String> = sidecar.sources.iter().map(|s| s.url.clone()).collect();
+
+    let mut out = String::from("<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n");
+    out.push_str("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n");
+    out.push_str(" <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n");
+    out.push_str("  <rdf:Description rdf:about=\"\"\n");
+    out.push_str("    xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n");
+    out.push_str("    xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"\n");
+    out.push_str("    xmlns:lr=\"http://ns.adobe.com/lightroom/1.0/\"\n");
+    if let Some(created) = sidecar.created {
+        out.push_str(&format!("    xmp:CreateDate=\"{}\"\n", created.to_rfc3339()));
+    }
+    out.push_str(&format!(
+        "    xmp:MetadataDate=\"{}\">\n",
+        chrono::Utc::now().to_rfc3339()
+    ));
+    xmp_alt(&mut out, "dc:title", sidecar.title);
+    xmp_alt(&mut out, "dc:description", sidecar.description);
+    xmp_list(&mut out, "dc:creator", "Seq", &sidecar.creators);
+    xmp_list(&mut out, "dc:subject", "Bag", &tags);
+    xmp_list(&mut out, "dc:relation", "Bag", &sources);
+    xmp_list(&mut out, "lr:hierarchicalSubject", "Bag", &hierarchical);
+    out.push_str("  </rdf:Description>\n </rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>\n");
+    out
+}
+
+impl SidecarFormats {
+    pub fn enabled(&self) -> bool {
+        self.json || self.xmp
+    }
+
+    /// Write the enabled sidecars for a media item
+    pub fn write(&self, storage_dir: &Path, item: &ApiMediaReturn) -> std::io::Result<()> {
+        let Some(storage_uri) = &item.storage_uri else {
+            return Ok(());
+        };
+        let mut tag_groups: BTreeMap<String, Vec<String>> = item
+            .tag_groups
+            .as_ref()
+            .map(|t| t.0.clone().into_iter().collect())
+            .unwrap_or_default();
+        tag_groups.values_mut().for_each(|t| t.sort());
+        let sidecar = JsonSidecar {
+            sha256: &item.sha256,
+            perceptual_hash: item.perceptual_hash,
+            file_type: item.file_type.as_deref(),
+            uploaded: item.uploaded,
+            created: item.created,
+            title: item.title.as_deref(),
+            description: item.description.as_deref(),
+            creators: item.creators.clone().unwrap_or_default(),
+            tag_groups,
+            sources: item.sources.as_ref().map(|s| s.0.clone()).unwrap_or_default(),
+            collections: item.collections.clone().unwrap_or_default(),
+            metadata: item.metadata.as_ref(),
+        };
+
+        if self.json {
+            write_atomic(
+                &sidecar_path(storage_dir, storage_uri, "json"),
+                &serde_json::to_vec_pretty(&sidecar)?,
+            )?;
+        }
+        if self.xmp {
+            write_atomic(
+                &sidecar_path(storage_dir, storage_uri, "xmp"),
+                xmp_document(&sidecar).as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Remove any sidecars of a stored file, whether or not they are still enabled
+    pub fn remove(&self, storage_dir: &Path, storage_uri: &str) {
+        for extension in ["json", "xmp"] {
+            std::fs::remove_file(sidecar_path(storage_dir, storage_uri, extension)).ok();
+        }
+    }
+
+    /// Rewrite the sidecars of the given media. A sidecar that can't be written is logged rather
+    /// than failing the change that caused it, as the database already holds that change
+    pub async fn sync(&self, storage_dir: &Path, ids: &[i64], db: &PgPool) -> anyhow::Result<()> {
+        if !self.enabled() || ids.is_empty() {
+            return Ok(());
+        }
+        let perceptual_hash: Option<BitVec> = None;
+        let items = sqlx::query_file_as!(
+            ApiMediaReturn,
+            "sql/media_item_get.sqlx",
+            ids,
+            perceptual_hash
+        )
+        .fetch_all(db)
+        .await?;
+        for item in items {
+            if let Err(e) = self.write(storage_dir, &item) {
+                tracing::error!("failed to write sidecar for media {:?}: {e}", item.id);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Rewrite the sidecars of every media item
+pub async fn sync_all(state: &AppState) -> anyhow::Result<usize> {
+    let ids = sqlx::query_scalar!("SELECT id FROM media ORDER BY id")
+        .fetch_all(&state.conn)
+        .await?;
+    for chunk in ids.chunks(500) {
+        state
+            .sidecars
+            .sync(&state.storage_dir, chunk, &state.conn)
+            .await?;
+    }
+    Ok(ids.len())
+}